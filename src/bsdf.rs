@@ -0,0 +1,598 @@
+use std::{f64::consts::PI, sync::Arc};
+
+use rand::{Rng, RngCore};
+
+use crate::{
+    drawing::Color,
+    geometry::Point,
+    material::{fresnel_dielectric, interface},
+    microfacet::{Fresnel, Microfacet},
+    sampling,
+    texture::{Texture, TexturePoint},
+    Float,
+};
+
+// a ray hitting a surface, everything a bsdf needs to know about it
+pub struct Hit {
+    // direction of the incoming ray
+    pub direction: Point,
+    // normal of the entity, it points inside
+    pub normal: Point,
+    // indices of refraction on both sides of the surface
+    pub outside: Float,
+    pub inside: Float,
+    // where the textures of the bsdf are looked up
+    pub at: TexturePoint,
+}
+
+impl Hit {
+    pub fn new(direction: Point, normal: Point, outside: Float, inside: Float) -> Self {
+        Self {
+            direction: direction.normalize(),
+            normal: normal.normalize(),
+            outside,
+            inside,
+            at: TexturePoint::new(Point::new(0.0, 0.0, 0.0), (0.0, 0.0)),
+        }
+    }
+
+    pub fn at(mut self, at: TexturePoint) -> Self {
+        self.at = at;
+        self
+    }
+
+    // normal flipped to the side of the surface the ray came from
+    pub fn facing_normal(&self) -> Point {
+        sampling::facing_normal(self.direction, self.normal)
+    }
+
+    fn cos(&self) -> Float {
+        (self.direction * self.normal).abs()
+    }
+}
+
+pub struct BsdfSample {
+    pub direction: Point,
+    // bsdf * cos / pdf, for specular samples the fraction of the light that goes along direction
+    pub weight: Color,
+    // solid angle density, None for perfectly specular directions that light sampling never finds
+    pub pdf: Option<Float>,
+    // the direction goes through the surface
+    pub refracted: bool,
+}
+
+// how a surface scatters light, the path tracer samples it and evaluates it for light samples,
+// the ray tracer evaluates it for light samples and follows all of its specular directions
+pub trait Bsdf: Send + Sync {
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample>;
+
+    // bsdf times the cosine towards to_light, zero for the specular parts
+    fn eval(&self, hit: &Hit, to_light: Point) -> Color;
+
+    // solid angle density of sample returning to_light, zero for the specular parts
+    fn pdf(&self, hit: &Hit, to_light: Point) -> Float;
+
+    // the perfectly specular directions with the fraction of the light along each of them, rough
+    // surfaces give the direction they scatter the most light in
+    fn specular(&self, _hit: &Hit) -> Vec<BsdfSample> {
+        Vec::new()
+    }
+
+    // diffusely reflected fraction of the light, the ray tracer lights it with the ambient light
+    fn albedo(&self, _hit: &Hit) -> Color {
+        Color::BLACK
+    }
+}
+
+pub struct Lambert {
+    pub albedo: Texture,
+}
+
+impl Bsdf for Lambert {
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let normal = hit.facing_normal();
+        let direction = sampling::cosine_hemisphere(normal, &mut *rng);
+        let cos = direction * normal;
+        if cos <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            weight: self.albedo.value(&hit.at),
+            pdf: Some(cos / PI),
+            refracted: false,
+        })
+    }
+
+    fn eval(&self, hit: &Hit, to_light: Point) -> Color {
+        self.albedo.value(&hit.at) * self.pdf(hit, to_light)
+    }
+
+    fn pdf(&self, hit: &Hit, to_light: Point) -> Float {
+        (to_light.normalize() * hit.facing_normal()).max(0.0) / PI
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.albedo.value(&hit.at)
+    }
+}
+
+pub struct Mirror {
+    pub tint: Texture,
+}
+
+impl Bsdf for Mirror {
+    fn sample(&self, hit: &Hit, _rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        Some(mirror(hit, self.tint.value(&hit.at)))
+    }
+
+    fn eval(&self, _hit: &Hit, _to_light: Point) -> Color {
+        Color::BLACK
+    }
+
+    fn pdf(&self, _hit: &Hit, _to_light: Point) -> Float {
+        0.0
+    }
+
+    fn specular(&self, hit: &Hit) -> Vec<BsdfSample> {
+        vec![mirror(hit, self.tint.value(&hit.at))]
+    }
+}
+
+// smooth glass, the refracted light is tinted
+pub struct SmoothDielectric {
+    pub tint: Texture,
+}
+
+impl Bsdf for SmoothDielectric {
+    // reflection or refraction is picked with the fresnel reflectance as probability
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let interface = interface(hit.direction, hit.normal, hit.outside, hit.inside);
+        match interface.refracted {
+            Some(refracted) if rng.gen::<Float>() >= interface.reflectance => Some(BsdfSample {
+                direction: refracted,
+                weight: self.tint.value(&hit.at),
+                pdf: None,
+                refracted: true,
+            }),
+            _ => Some(BsdfSample {
+                direction: interface.reflected,
+                weight: Color::WHITE,
+                pdf: None,
+                refracted: false,
+            }),
+        }
+    }
+
+    fn eval(&self, _hit: &Hit, _to_light: Point) -> Color {
+        Color::BLACK
+    }
+
+    fn pdf(&self, _hit: &Hit, _to_light: Point) -> Float {
+        0.0
+    }
+
+    fn specular(&self, hit: &Hit) -> Vec<BsdfSample> {
+        let interface = interface(hit.direction, hit.normal, hit.outside, hit.inside);
+        let mut lobes = vec![BsdfSample {
+            direction: interface.reflected,
+            weight: Color::WHITE * interface.reflectance,
+            pdf: None,
+            refracted: false,
+        }];
+        if let Some(refracted) = interface.refracted {
+            lobes.push(BsdfSample {
+                direction: refracted,
+                weight: self.tint.value(&hit.at) * (1.0 - interface.reflectance),
+                pdf: None,
+                refracted: true,
+            });
+        }
+        lobes
+    }
+}
+
+impl Bsdf for Microfacet {
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let sample = Microfacet::sample(
+            self,
+            hit.direction,
+            hit.normal,
+            hit.outside,
+            hit.inside,
+            &mut *rng,
+        )?;
+        Some(BsdfSample {
+            direction: sample.direction,
+            weight: sample.weight,
+            pdf: Some(sample.pdf),
+            refracted: sample.refracted,
+        })
+    }
+
+    fn eval(&self, hit: &Hit, to_light: Point) -> Color {
+        Microfacet::eval(
+            self,
+            hit.direction,
+            hit.normal,
+            to_light,
+            hit.outside,
+            hit.inside,
+        )
+    }
+
+    fn pdf(&self, hit: &Hit, to_light: Point) -> Float {
+        Microfacet::pdf(
+            self,
+            hit.direction,
+            hit.normal,
+            to_light,
+            hit.outside,
+            hit.inside,
+        )
+    }
+
+    // the ray tracer sees rough surfaces as smooth ones
+    fn specular(&self, hit: &Hit) -> Vec<BsdfSample> {
+        match *self {
+            Microfacet::Conductor { fresnel, .. } => {
+                vec![mirror(hit, fresnel.reflectance(hit.cos()))]
+            }
+            Microfacet::Dielectric { .. } => SmoothDielectric {
+                tint: Color::WHITE.into(),
+            }
+            .specular(hit),
+        }
+    }
+}
+
+// a microfacet surface with a roughness that varies over it
+pub struct Rough {
+    pub microfacet: Microfacet,
+    pub roughness: Texture,
+}
+
+impl Rough {
+    fn at(&self, hit: &Hit) -> Microfacet {
+        self.microfacet
+            .with_roughness(self.roughness.scalar(&hit.at).max(0.0))
+    }
+}
+
+impl Bsdf for Rough {
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        Bsdf::sample(&self.at(hit), hit, rng)
+    }
+
+    fn eval(&self, hit: &Hit, to_light: Point) -> Color {
+        Bsdf::eval(&self.at(hit), hit, to_light)
+    }
+
+    fn pdf(&self, hit: &Hit, to_light: Point) -> Float {
+        Bsdf::pdf(&self.at(hit), hit, to_light)
+    }
+
+    fn specular(&self, hit: &Hit) -> Vec<BsdfSample> {
+        self.microfacet.specular(hit)
+    }
+}
+
+// weight is the fraction of the light that goes to second
+pub struct Mix {
+    pub first: Arc<dyn Bsdf>,
+    pub second: Arc<dyn Bsdf>,
+    pub weight: Texture,
+}
+
+impl Mix {
+    // textured weights may leave [0, 1]
+    fn weight(&self, hit: &Hit) -> Float {
+        self.weight.scalar(&hit.at).clamp(0.0, 1.0)
+    }
+}
+
+impl Bsdf for Mix {
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let sample = if rng.gen::<Float>() < self.weight(hit) {
+            self.second.sample(hit, rng)?
+        } else {
+            self.first.sample(hit, rng)?
+        };
+        match sample.pdf {
+            // picking the lobe with the chance of its weight cancels the weight
+            None => Some(sample),
+            Some(_) => with_combined_pdf(self, hit, sample),
+        }
+    }
+
+    fn eval(&self, hit: &Hit, to_light: Point) -> Color {
+        let weight = self.weight(hit);
+        self.first.eval(hit, to_light) * (1.0 - weight) + self.second.eval(hit, to_light) * weight
+    }
+
+    fn pdf(&self, hit: &Hit, to_light: Point) -> Float {
+        let weight = self.weight(hit);
+        self.first.pdf(hit, to_light) * (1.0 - weight) + self.second.pdf(hit, to_light) * weight
+    }
+
+    fn specular(&self, hit: &Hit) -> Vec<BsdfSample> {
+        let weight = self.weight(hit);
+        let mut lobes = scaled(self.first.specular(hit), 1.0 - weight);
+        lobes.extend(scaled(self.second.specular(hit), weight));
+        lobes
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        let weight = self.weight(hit);
+        self.first.albedo(hit) * (1.0 - weight) + self.second.albedo(hit) * weight
+    }
+}
+
+// colors all of the scattered light
+pub struct Tinted {
+    pub bsdf: Arc<dyn Bsdf>,
+    pub tint: Texture,
+}
+
+impl Bsdf for Tinted {
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let sample = self.bsdf.sample(hit, rng)?;
+        Some(BsdfSample {
+            weight: sample.weight * self.tint.value(&hit.at),
+            ..sample
+        })
+    }
+
+    fn eval(&self, hit: &Hit, to_light: Point) -> Color {
+        self.bsdf.eval(hit, to_light) * self.tint.value(&hit.at)
+    }
+
+    fn pdf(&self, hit: &Hit, to_light: Point) -> Float {
+        self.bsdf.pdf(hit, to_light)
+    }
+
+    fn specular(&self, hit: &Hit) -> Vec<BsdfSample> {
+        let tint = self.tint.value(&hit.at);
+        self.bsdf
+            .specular(hit)
+            .into_iter()
+            .map(|lobe| BsdfSample {
+                weight: lobe.weight * tint,
+                ..lobe
+            })
+            .collect()
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.bsdf.albedo(hit) * self.tint.value(&hit.at)
+    }
+}
+
+// a base under a clear dielectric coat, like varnished wood or car paint
+//
+// the coat reflects by fresnel, what it lets through on the way in and on the way out reaches the
+// base, the base sees the directions outside of the coat since refraction at the coat is ignored
+pub struct Layered {
+    pub base: Arc<dyn Bsdf>,
+    coat: Microfacet,
+    refraction_coefficient: Float,
+}
+
+impl Layered {
+    pub fn new(base: Arc<dyn Bsdf>, roughness: Float, refraction_coefficient: Float) -> Self {
+        Self {
+            base,
+            coat: Microfacet::Conductor {
+                roughness,
+                fresnel: Fresnel::Dielectric(refraction_coefficient),
+            },
+            refraction_coefficient,
+        }
+    }
+
+    // fraction of the light that gets through the coat at an angle with cosine cos
+    fn transmittance(&self, cos: Float) -> Float {
+        1.0 - fresnel_dielectric(cos.abs(), 1.0, self.refraction_coefficient)
+    }
+
+    fn base_weight(&self, hit: &Hit, to_light: Point) -> Float {
+        self.transmittance(hit.cos()) * self.transmittance(to_light.normalize() * hit.normal)
+    }
+
+    // chance to sample the coat
+    fn coat_probability(&self, hit: &Hit) -> Float {
+        1.0 - self.transmittance(hit.cos())
+    }
+}
+
+impl Bsdf for Layered {
+    fn sample(&self, hit: &Hit, rng: &mut dyn RngCore) -> Option<BsdfSample> {
+        let coat = self.coat_probability(hit);
+        if rng.gen::<Float>() < coat {
+            let sample = Bsdf::sample(&self.coat, hit, rng)?;
+            with_combined_pdf(self, hit, sample)
+        } else {
+            let sample = self.base.sample(hit, rng)?;
+            match sample.pdf {
+                None => {
+                    let weight = self.base_weight(hit, sample.direction) / (1.0 - coat);
+                    Some(BsdfSample {
+                        weight: sample.weight * weight,
+                        ..sample
+                    })
+                }
+                Some(_) => with_combined_pdf(self, hit, sample),
+            }
+        }
+    }
+
+    fn eval(&self, hit: &Hit, to_light: Point) -> Color {
+        Bsdf::eval(&self.coat, hit, to_light)
+            + self.base.eval(hit, to_light) * self.base_weight(hit, to_light)
+    }
+
+    fn pdf(&self, hit: &Hit, to_light: Point) -> Float {
+        let coat = self.coat_probability(hit);
+        Bsdf::pdf(&self.coat, hit, to_light) * coat + self.base.pdf(hit, to_light) * (1.0 - coat)
+    }
+
+    fn specular(&self, hit: &Hit) -> Vec<BsdfSample> {
+        let mut lobes = Bsdf::specular(&self.coat, hit);
+        for lobe in self.base.specular(hit) {
+            let weight = self.base_weight(hit, lobe.direction);
+            lobes.push(BsdfSample {
+                weight: lobe.weight * weight,
+                ..lobe
+            });
+        }
+        lobes
+    }
+
+    fn albedo(&self, hit: &Hit) -> Color {
+        self.base.albedo(hit) * self.transmittance(hit.cos()).powi(2)
+    }
+}
+
+fn mirror(hit: &Hit, tint: Color) -> BsdfSample {
+    BsdfSample {
+        direction: hit.direction.reflect(hit.normal),
+        weight: tint,
+        pdf: None,
+        refracted: false,
+    }
+}
+
+// a sample of one lobe of a combined bsdf, reweighted as if the direction came from all of its
+// lobes, so a lobe that is rarely picked does not make fireflies
+fn with_combined_pdf(bsdf: &dyn Bsdf, hit: &Hit, sample: BsdfSample) -> Option<BsdfSample> {
+    let pdf = bsdf.pdf(hit, sample.direction);
+    if pdf <= 0.0 {
+        return None;
+    }
+    Some(BsdfSample {
+        weight: bsdf.eval(hit, sample.direction) * (1.0 / pdf),
+        pdf: Some(pdf),
+        ..sample
+    })
+}
+
+fn scaled(lobes: Vec<BsdfSample>, scale: Float) -> Vec<BsdfSample> {
+    lobes
+        .into_iter()
+        .filter(|_| scale > 0.0)
+        .map(|lobe| BsdfSample {
+            weight: lobe.weight * scale,
+            ..lobe
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    // the surface z = 0 of a solid below it, hit from above
+    fn hit(angle: Float) -> Hit {
+        Hit::new(
+            Point::new(angle.sin(), 0.0, -angle.cos()),
+            Point::new(0.0, 0.0, -1.0),
+            1.0,
+            1.5,
+        )
+    }
+
+    // mean weight of the samples, the fraction of the light that is scattered
+    fn reflectance(bsdf: &dyn Bsdf, hit: &Hit) -> Float {
+        let mut rng = StdRng::seed_from_u64(9);
+        let samples = 100_000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            if let Some(sample) = bsdf.sample(hit, &mut rng) {
+                total += sample.weight.channels()[0];
+            }
+        }
+        total / samples as Float
+    }
+
+    #[test]
+    fn lambert_reflects_its_albedo() {
+        let lambert = Lambert {
+            albedo: Color::new(0.5, 0.5, 0.5).into(),
+        };
+        assert!((reflectance(&lambert, &hit(0.7)) - 0.5).abs() < 1e-9);
+        let up = Point::new(0.3, 0.0, 1.0);
+        assert!(
+            (lambert.eval(&hit(0.7), up).channels()[0] - 0.5 * lambert.pdf(&hit(0.7), up)).abs()
+                < 1e-12
+        );
+        assert_eq!(lambert.pdf(&hit(0.7), Point::new(0.0, 0.0, -1.0)), 0.0);
+    }
+
+    #[test]
+    fn mix_samples_match_its_eval_and_pdf() {
+        let mix = Mix {
+            first: Arc::new(Lambert {
+                albedo: Color::WHITE.into(),
+            }),
+            second: Arc::new(Microfacet::Conductor {
+                roughness: 0.3,
+                fresnel: Fresnel::Schlick(Color::WHITE),
+            }),
+            weight: 0.25.into(),
+        };
+        let hit = hit(0.4);
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..1000 {
+            let sample = match mix.sample(&hit, &mut rng) {
+                None => continue,
+                Some(sample) => sample,
+            };
+            let pdf = mix.pdf(&hit, sample.direction);
+            assert!((sample.pdf.unwrap() - pdf).abs() < 1e-9 * pdf.max(1.0));
+            let weight = mix.eval(&hit, sample.direction).channels()[0] / pdf;
+            assert!((sample.weight.channels()[0] - weight).abs() < 1e-9 * weight.max(1.0));
+        }
+        // the rough white metal loses a little energy, lambert none
+        let total = reflectance(&mix, &hit);
+        assert!(total > 0.97 && total <= 1.0 + 1e-9, "{}", total);
+    }
+
+    #[test]
+    fn specular_lobes_of_glass_add_up() {
+        let glass = Mix {
+            first: Arc::new(Lambert {
+                albedo: Color::WHITE.into(),
+            }),
+            second: Arc::new(SmoothDielectric {
+                tint: Color::WHITE.into(),
+            }),
+            weight: 0.8.into(),
+        };
+        let lobes = glass.specular(&hit(0.6));
+        assert_eq!(lobes.len(), 2);
+        assert!(lobes[1].refracted);
+        let total: Float = lobes.iter().map(|lobe| lobe.weight.channels()[0]).sum();
+        assert!((total - 0.8).abs() < 1e-12);
+        assert!((glass.albedo(&hit(0.6)).channels()[0] - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn coat_over_lambert_conserves_energy() {
+        let coated = Layered::new(
+            Arc::new(Lambert {
+                albedo: Color::WHITE.into(),
+            }),
+            0.2,
+            1.5,
+        );
+        for &angle in [0.1, 0.8, 1.4].iter() {
+            let hit = hit(angle);
+            let total = reflectance(&coated, &hit);
+            assert!(total < 1.0 && total > 0.8, "{} {}", angle, total);
+        }
+        // the coat reflects more at grazing angles
+        assert!(coated.coat_probability(&hit(1.4)) > coated.coat_probability(&hit(0.1)));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{
+    geometry::Point,
+    texture::{Texture, TexturePoint},
+    Float,
+};
+
+// step along the texture coordinates for the slope of height maps
+const DELTA: Float = 0.0001;
+
+// bends the normal the bsdf sees, the entity keeps its shape
+#[derive(Clone)]
+pub enum Bump {
+    // height of the surface above the entity, the scalar of the texture times strength in units
+    // of distance
+    Height { height: Texture, strength: Float },
+    // normals in the tangent frame stored as colors like in normal map images, red goes along u,
+    // green along v and blue away from the surface, so flat is (0.5, 0.5, 1)
+    Normal(Texture),
+}
+
+impl Bump {
+    // normal of the entity, which points inside, bent at the point, tangents are the derivatives
+    // of the point along the texture coordinates, any frame is used without them
+    pub fn normal(
+        &self,
+        normal: Point,
+        tangents: Option<(Point, Point)>,
+        at: &TexturePoint,
+    ) -> Point {
+        let outward = normal.normalize() * -1.0;
+        let (dpdu, dpdv) = match tangents {
+            Some((dpdu, dpdv)) if dpdu.dot(dpdv).len() > 0.000000000001 => (dpdu, dpdv),
+            _ => outward.orthonormal_basis(),
+        };
+        let bent = match self {
+            Bump::Height { height, strength } => {
+                let height = |du: Float, dv: Float| {
+                    let at = TexturePoint::new(
+                        at.point + dpdu * du + dpdv * dv,
+                        (at.uv.0 + du, at.uv.1 + dv),
+                    );
+                    height.scalar(&at) * strength
+                };
+                let base = height(0.0, 0.0);
+                let dhdu = (height(DELTA, 0.0) - base) / DELTA;
+                let dhdv = (height(0.0, DELTA) - base) / DELTA;
+                // the surface moved along the normal, its tangents tilt with the slope
+                let bent = (dpdu + outward * dhdu).dot(dpdv + outward * dhdv);
+                if dpdu.dot(dpdv) * outward < 0.0 {
+                    bent * -1.0
+                } else {
+                    bent
+                }
+            }
+            Bump::Normal(texture) => {
+                let [r, g, b] = texture.value(at).channels();
+                let tangent = (dpdu - outward * (outward * dpdu)).normalize();
+                let bitangent =
+                    (dpdv - outward * (outward * dpdv) - tangent * (tangent * dpdv)).normalize();
+                tangent * (2.0 * r - 1.0) + bitangent * (2.0 * g - 1.0) + outward * (2.0 * b - 1.0)
+            }
+        };
+        // degenerate maps keep the normal of the entity
+        let len = bent.len();
+        if len.is_nan() || len < 0.000000000001 {
+            return normal;
+        }
+        bent.normalize() * -1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{drawing::Color, texture::Procedural};
+    use std::sync::Arc;
+
+    // the z = 0 plane seen from above, u along x and v along y, its normal points down
+    const NORMAL: Point = Point {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+    };
+
+    fn tangents() -> Option<(Point, Point)> {
+        Some((Point::new(1.0, 0.0, 0.0), Point::new(0.0, 1.0, 0.0)))
+    }
+
+    fn at(x: Float, y: Float) -> TexturePoint {
+        TexturePoint::new(Point::new(x, y, 0.0), (x, y))
+    }
+
+    fn assert_close(actual: Point, expected: Point) {
+        assert!(
+            (actual - expected).len() < 1e-6,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn flat_maps_keep_the_normal() {
+        let height = Bump::Height {
+            height: 0.3.into(),
+            strength: 2.0,
+        };
+        assert_close(height.normal(NORMAL, tangents(), &at(0.2, 0.7)), NORMAL);
+        let normal = Bump::Normal(Color::new(0.5, 0.5, 1.0).into());
+        assert_close(normal.normal(NORMAL, tangents(), &at(0.2, 0.7)), NORMAL);
+        assert_close(normal.normal(NORMAL, None, &at(0.2, 0.7)), NORMAL);
+    }
+
+    #[test]
+    fn slopes_tilt_the_normal() {
+        // rises along x with slope 1, the outward normal leans back towards -x
+        let ramp = |at: &TexturePoint| Color::new(at.point.x, at.point.x, at.point.x);
+        let texture = Texture::Procedural(Arc::new(ramp) as Arc<dyn Procedural>);
+        let bump = Bump::Height {
+            height: texture,
+            strength: 1.0,
+        };
+        let expected = Point::new(1.0, 0.0, -1.0).normalize();
+        assert_close(bump.normal(NORMAL, tangents(), &at(0.5, 0.5)), expected);
+    }
+
+    #[test]
+    fn normal_maps_follow_the_tangents() {
+        // leans towards +v, so the inside normal leans towards -y
+        let bump = Bump::Normal(Color::new(0.5, 1.0, 0.5).into());
+        assert_close(
+            bump.normal(NORMAL, tangents(), &at(0.0, 0.0)),
+            Point::new(0.0, -1.0, 0.0),
+        );
+        let flipped = Some((Point::new(1.0, 0.0, 0.0), Point::new(0.0, -1.0, 0.0)));
+        assert_close(
+            bump.normal(NORMAL, flipped, &at(0.0, 0.0)),
+            Point::new(0.0, 1.0, 0.0),
+        );
+    }
+}
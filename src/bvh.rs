@@ -0,0 +1,241 @@
+use crate::{
+    entities::IntersectionResult,
+    geometry::{Aabb, Point},
+    Float,
+};
+
+const LEAF_SIZE: usize = 4;
+
+// relative cost of one box test compared to one entity intersection
+const TRAVERSAL_COST: Float = 0.5;
+
+#[derive(Clone)]
+enum Node {
+    Leaf {
+        bounds: Aabb,
+        first: usize,
+        count: usize,
+    },
+    Inner {
+        bounds: Aabb,
+        left: usize,
+        right: usize,
+    },
+}
+
+impl Node {
+    fn bounds(&self) -> Aabb {
+        match self {
+            Node::Leaf { bounds, .. } => *bounds,
+            Node::Inner { bounds, .. } => *bounds,
+        }
+    }
+}
+
+// bounding volume hierarchy over entity indices, built with the surface area heuristic
+#[derive(Clone)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+}
+
+struct BuildItem {
+    index: usize,
+    bounds: Aabb,
+    center: Point,
+}
+
+impl Bvh {
+    pub fn new(bounded: Vec<(usize, Aabb)>) -> Self {
+        let mut items: Vec<BuildItem> = bounded
+            .into_iter()
+            .map(|(index, bounds)| {
+                let bounds = pad(bounds);
+                BuildItem {
+                    index,
+                    bounds,
+                    center: bounds.center(),
+                }
+            })
+            .collect();
+        let mut bvh = Self {
+            nodes: Vec::new(),
+            indices: Vec::with_capacity(items.len()),
+        };
+        if !items.is_empty() {
+            bvh.build(&mut items);
+        }
+        bvh
+    }
+
+    fn build(&mut self, items: &mut [BuildItem]) -> usize {
+        let bounds = items
+            .iter()
+            .fold(Aabb::empty(), |acc, item| acc.union(item.bounds));
+        let node_idx = self.nodes.len();
+
+        let split = if items.len() <= LEAF_SIZE {
+            None
+        } else {
+            best_split(items, bounds)
+        };
+
+        match split {
+            None => {
+                let first = self.indices.len();
+                self.indices.extend(items.iter().map(|item| item.index));
+                self.nodes.push(Node::Leaf {
+                    bounds,
+                    first,
+                    count: items.len(),
+                });
+            }
+            Some((axis, position)) => {
+                sort_by_axis(items, axis);
+                self.nodes.push(Node::Leaf {
+                    bounds,
+                    first: 0,
+                    count: 0,
+                });
+                let (left_items, right_items) = items.split_at_mut(position);
+                let left = self.build(left_items);
+                let right = self.build(right_items);
+                self.nodes[node_idx] = Node::Inner {
+                    bounds,
+                    left,
+                    right,
+                };
+            }
+        }
+        node_idx
+    }
+
+    // calls intersect for every entity whose box the ray may hit before the current closest distance,
+    // intersect returns the distance of a new closest hit so that farther nodes can be skipped
+    pub fn traverse<F>(
+        &self,
+        origin: Point,
+        direction: Point,
+        max_distance: Float,
+        mut intersect: F,
+    ) where
+        F: FnMut(usize) -> Option<Float>,
+    {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let length = direction.len();
+        let inv_direction = Point::new(1.0 / direction.x, 1.0 / direction.y, 1.0 / direction.z);
+        let mut max_t = max_distance / length;
+        let mut stack = vec![0];
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            if node
+                .bounds()
+                .intersect(origin, inv_direction, max_t)
+                .is_none()
+            {
+                continue;
+            }
+            match node {
+                Node::Leaf { first, count, .. } => {
+                    for &index in &self.indices[*first..*first + *count] {
+                        if let Some(distance) = intersect(index) {
+                            max_t = max_t.min(distance / length);
+                        }
+                    }
+                }
+                Node::Inner { left, right, .. } => {
+                    let left_t = self.nodes[*left]
+                        .bounds()
+                        .intersect(origin, inv_direction, max_t);
+                    let right_t =
+                        self.nodes[*right]
+                            .bounds()
+                            .intersect(origin, inv_direction, max_t);
+                    // push the farther child first so the nearer one is visited first
+                    match (left_t, right_t) {
+                        (Some(l), Some(r)) if l > r => {
+                            stack.push(*left);
+                            stack.push(*right);
+                        }
+                        (Some(_), Some(_)) => {
+                            stack.push(*right);
+                            stack.push(*left);
+                        }
+                        (Some(_), None) => stack.push(*left),
+                        (None, Some(_)) => stack.push(*right),
+                        (None, None) => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+// returns the axis and the number of items in the left half, or None if a leaf is cheaper
+fn best_split(items: &mut [BuildItem], bounds: Aabb) -> Option<(usize, usize)> {
+    let count = items.len();
+    let parent_area = bounds.surface_area();
+    if parent_area <= 0.0 {
+        return None;
+    }
+    let mut best_cost = count as Float;
+    let mut best = None;
+    let mut right_areas = vec![0.0; count];
+    for axis in 0..3 {
+        sort_by_axis(items, axis);
+
+        let mut right_bounds = Aabb::empty();
+        for i in (1..count).rev() {
+            right_bounds = right_bounds.union(items[i].bounds);
+            right_areas[i] = right_bounds.surface_area();
+        }
+
+        let mut left_bounds = Aabb::empty();
+        for i in 1..count {
+            left_bounds = left_bounds.union(items[i - 1].bounds);
+            let cost = TRAVERSAL_COST
+                + (left_bounds.surface_area() * i as Float + right_areas[i] * (count - i) as Float)
+                    / parent_area;
+            if cost < best_cost {
+                best_cost = cost;
+                best = Some((axis, i));
+            }
+        }
+    }
+    best
+}
+
+fn sort_by_axis(items: &mut [BuildItem], axis: usize) {
+    items.sort_by(|a, b| {
+        a.center
+            .axis(axis)
+            .partial_cmp(&b.center.axis(axis))
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.index.cmp(&b.index))
+    });
+}
+
+// entities that are flat along some axis still need a box with some thickness
+fn pad(bounds: Aabb) -> Aabb {
+    let size = bounds.max - bounds.min;
+    let epsilon = 0.000001 * (1.0 + size.x.abs().max(size.y.abs()).max(size.z.abs()));
+    let padding = Point::new(epsilon, epsilon, epsilon);
+    Aabb::new(bounds.min - padding, bounds.max + padding)
+}
+
+// keeps the closest hit, preferring the entity that comes first in the scene on ties
+pub fn closer(
+    current: &Option<(usize, IntersectionResult)>,
+    index: usize,
+    candidate: &IntersectionResult,
+) -> bool {
+    match current {
+        None => true,
+        Some((current_index, current)) => {
+            candidate.distance < current.distance
+                || (candidate.distance == current.distance && index < *current_index)
+        }
+    }
+}
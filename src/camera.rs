@@ -0,0 +1,94 @@
+use crate::{geometry::Point, Float};
+
+// pinhole camera, image x goes along `right` and image y goes against `up`
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    eye: Point,
+    forward: Point,
+    right: Point,
+    up: Point,
+    // vertical field of view in radians
+    fov: Float,
+    // width / height, taken from the image size if None
+    aspect_ratio: Option<Float>,
+}
+
+impl Camera {
+    // fails if the camera does not look anywhere, if up is parallel to the view or if the field
+    // of view is not between 0 and 180 degrees
+    pub fn new(eye: Point, target: Point, up: Point, fov: Float) -> Result<Self, String> {
+        let view = target - eye;
+        if view.len() < 0.000000001 {
+            return Err("the target is at the eye".to_string());
+        }
+        let forward = view.normalize();
+        let right = forward.dot(up);
+        if right.len() < 0.000000001 * up.len() || up.len() < 0.000000001 {
+            return Err("up is parallel to the view direction".to_string());
+        }
+        if !(fov > 0.0 && fov < std::f64::consts::PI) {
+            return Err("the field of view must be between 0 and 180 degrees".to_string());
+        }
+        let right = right.normalize();
+        let up = right.dot(forward);
+        Ok(Self {
+            eye,
+            forward,
+            right,
+            up,
+            fov,
+            aspect_ratio: None,
+        })
+    }
+
+    pub fn with_aspect_ratio(mut self, aspect_ratio: Float) -> Self {
+        self.aspect_ratio = Some(aspect_ratio);
+        self
+    }
+
+    pub fn eye(&self) -> Point {
+        self.eye
+    }
+
+    // x and y are image coordinates in pixels, may be fractional
+    pub fn ray(&self, x: Float, y: Float, width: usize, height: usize) -> Point {
+        let aspect_ratio = self
+            .aspect_ratio
+            .unwrap_or(width as Float / height as Float);
+        let half_height = (self.fov / 2.0).tan();
+        let half_width = half_height * aspect_ratio;
+        let u = (x / width as Float) * 2.0 - 1.0;
+        let v = (y / height as Float) * 2.0 - 1.0;
+        (self.forward + self.right * (u * half_width) - self.up * (v * half_height)).normalize()
+    }
+}
+
+impl Default for Camera {
+    // looks along z from the origin with y pointing down on the image, like the old fixed projection
+    fn default() -> Self {
+        Self::new(
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(0.0, 0.0, 1.0),
+            Point::new(0.0, -1.0, 0.0),
+            std::f64::consts::FRAC_PI_2,
+        )
+        .expect("the default camera is valid")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn degenerate_cameras_are_rejected() {
+        let eye = Point::new(0.0, 0.0, 0.0);
+        let target = Point::new(0.0, 0.0, 1.0);
+        let up = Point::new(0.0, -1.0, 0.0);
+        assert!(Camera::new(eye, target, up, 1.0).is_ok());
+        assert!(Camera::new(eye, target, Point::new(0.0, 0.0, -2.0), 1.0).is_err());
+        assert!(Camera::new(eye, eye, up, 1.0).is_err());
+        assert!(Camera::new(eye, target, up, 0.0).is_err());
+        assert!(Camera::new(eye, target, up, 4.0).is_err());
+    }
+}
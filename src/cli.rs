@@ -0,0 +1,301 @@
+use std::str::FromStr;
+
+use crate::{drawing::ToneMapper, filter::Filter, scene};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Renderer {
+    // whitted style ray tracer, trace::trace_parallel
+    Ray,
+    // progressive path tracer, trace::path_trace_with_progress
+    Path,
+}
+
+#[derive(Debug)]
+pub struct Options {
+    pub renderer: Renderer,
+    pub scene: String,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub output: Option<String>,
+    pub threads: Option<usize>,
+    pub samples: Option<usize>,
+    // seconds
+    pub time: Option<f64>,
+    pub max_depth: Option<usize>,
+    pub supersampling: Option<usize>,
+    pub filter: Option<Filter>,
+    pub light_samples: Option<usize>,
+    // stops
+    pub exposure: Option<f64>,
+    pub tone_mapper: Option<ToneMapper>,
+    pub white: Option<f64>,
+    // hdr image the result is compared against
+    pub reference: Option<String>,
+    // hdr image of an earlier path traced render and its samples per pixel
+    pub resume: Option<(String, usize)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            renderer: Renderer::Path,
+            scene: "scene_2".to_string(),
+            width: None,
+            height: None,
+            output: None,
+            threads: None,
+            samples: None,
+            time: None,
+            max_depth: None,
+            supersampling: None,
+            filter: None,
+            light_samples: None,
+            exposure: None,
+            tone_mapper: None,
+            white: None,
+            reference: None,
+            resume: None,
+        }
+    }
+}
+
+pub enum Command {
+    Help,
+    Render(Box<Options>),
+}
+
+pub fn usage() -> String {
+    format!(
+        "\
+usage: ray-tracing-1 [options]
+
+options:
+    -r, --renderer <ray|path>   renderer to use (default: path)
+    -s, --scene <name|file>     built-in scene ({}) or scene file (default: scene_2)
+    -w, --width <pixels>        image width (default: from the scene)
+    -h, --height <pixels>       image height (default: from the scene)
+    -o, --output <file>         output image (default: result.png or path_result.png)
+                                .exr, .pfm and .hdr files keep the unclamped radiance
+    -t, --threads <count>       number of worker threads (default: number of cpus)
+        --samples <count>       samples per pixel of the path tracer
+        --time <seconds>        time budget of the path tracer
+                                the path tracer runs forever if neither --samples nor --time is set
+        --max-depth <count>     maximal number of bounces (default: from the scene)
+        --aa <n>                n x n jittered rays per pixel of the ray tracer (default: 1)
+        --filter <filter>       box, tent, gaussian or mitchell, filter of the ray tracer
+                                (default: box)
+        --light-samples <count> shadow rays of the ray tracer per pixel and area light
+                                (default: 16)
+        --exposure <stops>      exposure of 8 bit images (default: 0)
+        --tone-map <mapper>     clamp, reinhard, extended-reinhard, aces or uncharted2
+                                (default: clamp)
+        --white <radiance>      radiance that becomes white with extended-reinhard and
+                                uncharted2 (default: brightest pixel or 11.2)
+        --reference <file>      print the rms error against an .exr, .pfm or .hdr image
+        --resume <file> <count> continue a path traced .exr, .pfm or .hdr image that has
+                                count samples per pixel, --samples includes them
+        --help                  print this message",
+        scene::builtin_names().join(", ")
+    )
+}
+
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        let mut value = || match args.next() {
+            Some(value) => Ok(value),
+            None => Err(format!("missing value for {}", arg)),
+        };
+        match arg.as_str() {
+            "--help" => return Ok(Command::Help),
+            "-r" | "--renderer" => {
+                options.renderer = match value()?.as_str() {
+                    "ray" => Renderer::Ray,
+                    "path" => Renderer::Path,
+                    other => return Err(format!("unknown renderer '{}'", other)),
+                }
+            }
+            "-s" | "--scene" => options.scene = value()?,
+            "-w" | "--width" => options.width = Some(number(&arg, &value()?)?),
+            "-h" | "--height" => options.height = Some(number(&arg, &value()?)?),
+            "-o" | "--output" => options.output = Some(value()?),
+            "-t" | "--threads" => {
+                // zero threads would never render anything
+                let threads: usize = number(&arg, &value()?)?;
+                options.threads = Some(threads.max(1))
+            }
+            "--samples" => options.samples = Some(number(&arg, &value()?)?),
+            "--time" => {
+                let time: f64 = number(&arg, &value()?)?;
+                if !time.is_finite() || time < 0.0 {
+                    return Err(format!("invalid value '{}' for {}", time, arg));
+                }
+                options.time = Some(time)
+            }
+            "--max-depth" => options.max_depth = Some(number(&arg, &value()?)?),
+            "--aa" => {
+                let supersampling = number(&arg, &value()?)?;
+                if supersampling == 0 {
+                    return Err(format!("invalid value '{}' for {}", supersampling, arg));
+                }
+                options.supersampling = Some(supersampling)
+            }
+            "--filter" => {
+                options.filter = Some(match value()?.as_str() {
+                    "box" => Filter::Box,
+                    "tent" => Filter::Tent,
+                    "gaussian" => Filter::Gaussian,
+                    "mitchell" => Filter::Mitchell,
+                    other => return Err(format!("unknown filter '{}'", other)),
+                })
+            }
+            "--light-samples" => {
+                let samples = number(&arg, &value()?)?;
+                if samples == 0 {
+                    return Err(format!("invalid value '{}' for {}", samples, arg));
+                }
+                options.light_samples = Some(samples)
+            }
+            "--exposure" => {
+                let exposure: f64 = number(&arg, &value()?)?;
+                if !exposure.is_finite() {
+                    return Err(format!("invalid value '{}' for {}", exposure, arg));
+                }
+                options.exposure = Some(exposure)
+            }
+            "--tone-map" => {
+                options.tone_mapper = Some(match value()?.as_str() {
+                    "clamp" => ToneMapper::Clamp,
+                    "reinhard" => ToneMapper::Reinhard,
+                    "extended-reinhard" => ToneMapper::ExtendedReinhard,
+                    "aces" => ToneMapper::Aces,
+                    "uncharted2" => ToneMapper::Uncharted2,
+                    other => return Err(format!("unknown tone mapper '{}'", other)),
+                })
+            }
+            "--white" => {
+                let white: f64 = number(&arg, &value()?)?;
+                if !white.is_finite() || white <= 0.0 {
+                    return Err(format!("invalid value '{}' for {}", white, arg));
+                }
+                options.white = Some(white)
+            }
+            "--reference" => options.reference = Some(value()?),
+            "--resume" => {
+                let file = value()?;
+                let samples = number(&arg, &value()?)?;
+                if samples == 0 {
+                    return Err(format!("invalid value '{}' for {}", samples, arg));
+                }
+                options.resume = Some((file, samples))
+            }
+            _ => return Err(format!("unknown argument '{}'", arg)),
+        }
+    }
+    if options.resume.is_some() && options.renderer != Renderer::Path {
+        return Err("only path traced images can be resumed".to_string());
+    }
+    Ok(Command::Render(Box::new(options)))
+}
+
+fn number<T: FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, arg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn options(args: &[&str]) -> Options {
+        match parse_args(args) {
+            Ok(Command::Render(options)) => *options,
+            Ok(Command::Help) => panic!("{:?} asked for help", args),
+            Err(error) => panic!("{:?} failed: {}", args, error),
+        }
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        let options = options(&[]);
+        assert_eq!(options.renderer, Renderer::Path);
+        assert_eq!(options.scene, "scene_2");
+        assert!(options.width.is_none() && options.threads.is_none());
+    }
+
+    #[test]
+    fn short_h_is_the_height() {
+        let options = options(&["-r", "ray", "-w", "320", "-h", "240", "-s", "scene_1"]);
+        assert_eq!(options.renderer, Renderer::Ray);
+        assert_eq!(options.width, Some(320));
+        assert_eq!(options.height, Some(240));
+        assert_eq!(options.scene, "scene_1");
+        assert!(matches!(
+            parse_args(&["-w", "1", "--help"]),
+            Ok(Command::Help)
+        ));
+    }
+
+    #[test]
+    fn values_are_parsed() {
+        let options = options(&[
+            "-o",
+            "out.png",
+            "--samples",
+            "8",
+            "--max-depth",
+            "3",
+            "--time",
+            "1.5",
+            "--aa",
+            "3",
+            "--filter",
+            "mitchell",
+            "--light-samples",
+            "4",
+            "--tone-map",
+            "aces",
+            "--resume",
+            "old.exr",
+            "16",
+        ]);
+        assert_eq!(options.output, Some("out.png".to_string()));
+        assert_eq!(options.samples, Some(8));
+        assert_eq!(options.time, Some(1.5));
+        assert_eq!(options.supersampling, Some(3));
+        assert_eq!(options.filter, Some(Filter::Mitchell));
+        assert_eq!(options.light_samples, Some(4));
+        assert_eq!(options.tone_mapper, Some(ToneMapper::Aces));
+        assert_eq!(options.resume, Some(("old.exr".to_string(), 16)));
+        assert_eq!(options.max_depth, Some(3));
+    }
+
+    #[test]
+    fn zero_threads_are_clamped() {
+        assert_eq!(options(&["--threads", "0"]).threads, Some(1));
+        assert_eq!(options(&["-t", "4"]).threads, Some(4));
+    }
+
+    #[test]
+    fn invalid_arguments_are_errors() {
+        let error = |args: &[&str]| parse_args(args).err().unwrap();
+        assert_eq!(error(&["-h"]), "missing value for -h");
+        assert_eq!(
+            error(&["--width", "wide"]),
+            "invalid value 'wide' for --width"
+        );
+        assert_eq!(error(&["-r", "raster"]), "unknown renderer 'raster'");
+        assert_eq!(error(&["--frobnicate"]), "unknown argument '--frobnicate'");
+        assert_eq!(error(&["--aa", "0"]), "invalid value '0' for --aa");
+        assert_eq!(error(&["--time", "-1"]), "invalid value '-1' for --time");
+        assert_eq!(
+            error(&["-r", "ray", "--resume", "old.exr", "4"]),
+            "only path traced images can be resumed"
+        );
+    }
+}
@@ -1,10 +1,10 @@
-use std::ops::{Add, AddAssign, Mul, MulAssign};
+use std::ops::{Add, Mul, MulAssign};
 
 use image::{Rgb, RgbImage};
 
 use crate::Float;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Color {
     r: Float,
     g: Float,
@@ -49,18 +49,90 @@ impl Color {
         Self::new(r as Float / 255.0, g as Float / 255.0, b as Float / 255.0)
     }
 
-    pub fn to_rgb(&self) -> Rgb<u8> {
-        Rgb([to_byte(self.r), to_byte(self.g), to_byte(self.b)])
+    pub fn channels(self) -> [Float; 3] {
+        [self.r, self.g, self.b]
+    }
+}
+
+// compresses linear radiance into [0, 1] before the srgb transfer function
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMapper {
+    // hard clip at 1
+    Clamp,
+    // x / (1 + x)
+    Reinhard,
+    // reinhard that maps the white point to 1
+    ExtendedReinhard,
+    // narkowicz's fit of the aces filmic curve
+    Aces,
+    // hable's filmic curve with an exposure bias of 2, white still maps to 1
+    Uncharted2,
+}
+
+impl ToneMapper {
+    pub fn apply(self, x: Float, white: Float) -> Float {
+        match self {
+            ToneMapper::Clamp => x,
+            ToneMapper::Reinhard => x / (1.0 + x),
+            ToneMapper::ExtendedReinhard => x * (1.0 + x / (white * white)) / (1.0 + x),
+            ToneMapper::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+            ToneMapper::Uncharted2 => hable(2.0 * x) / hable(2.0 * white),
+        }
+    }
+}
+
+fn hable(x: Float) -> Float {
+    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+    ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f
+}
+
+// turns linear radiance into displayable srgb bytes
+#[derive(Clone, Copy, Debug)]
+pub struct OutputTransform {
+    // in stops, every stop doubles the radiance
+    pub exposure: Float,
+    pub tone_mapper: ToneMapper,
+    // radiance that becomes white, None is the brightest channel of the image for
+    // extended reinhard and 11.2 for uncharted 2
+    pub white: Option<Float>,
+}
+
+impl Default for OutputTransform {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapper: ToneMapper::Clamp,
+            white: None,
+        }
+    }
+}
+
+impl OutputTransform {
+    fn apply(&self, color: Color, white: Float) -> Rgb<u8> {
+        let scale = Float::powf(2.0, self.exposure);
+        let channel = |x: Float| {
+            let x = (x * scale).max(0.0);
+            (srgb_oetf(self.tone_mapper.apply(x, white).min(1.0)) * 255.0).round() as u8
+        };
+        Rgb([channel(color.r), channel(color.g), channel(color.b)])
+    }
+}
+
+// encodes linear values in [0, 1] with the srgb transfer function
+pub fn srgb_oetf(x: Float) -> Float {
+    if x <= 0.0031308 {
+        12.92 * x
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
     }
 }
 
-fn to_byte(number: Float) -> u8 {
-    if number >= 1.0 {
-        255
-    } else if number <= 0.0 {
-        0
+// decodes srgb values in [0, 1] into linear ones
+pub fn srgb_eotf(x: Float) -> Float {
+    if x <= 0.04045 {
+        x / 12.92
     } else {
-        (number * 255.0) as u8
+        ((x + 0.055) / 1.055).powf(2.4)
     }
 }
 
@@ -124,6 +196,14 @@ impl ColorMatrix {
         ColorMatrix { matrix }
     }
 
+    pub fn width(&self) -> usize {
+        self.matrix[0].len()
+    }
+
+    pub fn height(&self) -> usize {
+        self.matrix.len()
+    }
+
     pub fn get(&self, x: usize, y: usize) -> Color {
         self.matrix[y][x]
     }
@@ -132,42 +212,106 @@ impl ColorMatrix {
         self.matrix[y][x] = c;
     }
 
-    pub fn to_image(&self) -> RgbImage {
+    pub fn to_image(&self, transform: &OutputTransform) -> RgbImage {
         let width = self.matrix[0].len();
         let height = self.matrix.len();
+        let white = match (transform.white, transform.tone_mapper) {
+            (Some(white), _) => white,
+            (None, ToneMapper::Uncharted2) => 11.2,
+            (None, _) => self.brightest() * Float::powf(2.0, transform.exposure),
+        }
+        .max(Float::EPSILON);
         let mut img = RgbImage::new(width as u32, height as u32);
         for x in 0..width {
             for y in 0..height {
-                img.put_pixel(x as u32, y as u32, self.get(x, y).to_rgb());
+                img.put_pixel(x as u32, y as u32, transform.apply(self.get(x, y), white));
             }
         }
         img
     }
 
-    pub fn add_iteration(&mut self, other: ColorMatrix, iteration: usize) {
-        let width = self.matrix[0].len();
-        let height = self.matrix.len();
-        for x in 0..width {
-            for y in 0..height {
-                self.set(
-                    x,
-                    y,
-                    (self.get(x, y) * iteration as Float + other.get(x, y))
-                        * (1.0 / (iteration + 1) as Float),
-                );
+    fn brightest(&self) -> Float {
+        self.matrix
+            .iter()
+            .flatten()
+            .map(|color| color.r.max(color.g).max(color.b))
+            .fold(0.0, Float::max)
+    }
+
+    // root mean square error over all channels, the sizes have to match
+    pub fn rmse(&self, other: &ColorMatrix) -> Float {
+        let mut sum = 0.0;
+        for x in 0..self.width() {
+            for y in 0..self.height() {
+                let a = self.get(x, y);
+                let b = other.get(x, y);
+                sum += (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2);
             }
         }
+        (sum / (self.width() * self.height() * 3) as Float).sqrt()
     }
 }
 
-impl AddAssign<ColorMatrix> for ColorMatrix {
-    fn add_assign(&mut self, other: ColorMatrix) {
-        let width = self.matrix[0].len();
-        let height = self.matrix.len();
-        for x in 0..width {
-            for y in 0..height {
-                self.set(x, y, self.get(x, y) + other.get(x, y));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_transfer_function() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-12);
+        assert!((srgb_oetf(0.002) - 0.02584).abs() < 1e-9);
+        assert!((srgb_oetf(0.5) - 0.735357).abs() < 1e-6);
+        // both pieces meet at the threshold
+        assert!(
+            (12.92 * 0.0031308 - (1.055 * Float::powf(0.0031308, 1.0 / 2.4) - 0.055)).abs() < 1e-6
+        );
+    }
+
+    #[test]
+    fn tone_mappers_are_monotonic_and_map_white() {
+        let mappers = [
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::ExtendedReinhard,
+            ToneMapper::Aces,
+            ToneMapper::Uncharted2,
+        ];
+        for &mapper in mappers.iter() {
+            assert!(mapper.apply(0.0, 4.0).abs() < 1e-9, "{:?}", mapper);
+            let mut previous = 0.0;
+            for i in 1..400 {
+                let value = mapper.apply(i as Float * 0.01, 4.0);
+                assert!(value > previous, "{:?}", mapper);
+                previous = value;
             }
         }
+        assert!((ToneMapper::ExtendedReinhard.apply(4.0, 4.0) - 1.0).abs() < 1e-12);
+        assert!((ToneMapper::Uncharted2.apply(4.0, 4.0) - 1.0).abs() < 1e-12);
+        assert!(ToneMapper::Reinhard.apply(1000.0, 4.0) < 1.0);
+    }
+
+    #[test]
+    fn images_are_exposed_and_encoded() {
+        let mut matrix = ColorMatrix::new(2, 1);
+        matrix.set(0, 0, Color::new(0.25, 4.0, -1.0));
+        matrix.set(1, 0, Color::new(0.5, 0.0, 0.0));
+
+        let image = matrix.to_image(&OutputTransform::default());
+        assert_eq!(*image.get_pixel(0, 0), Rgb([137, 255, 0]));
+        assert_eq!(*image.get_pixel(1, 0), Rgb([188, 0, 0]));
+
+        let image = matrix.to_image(&OutputTransform {
+            exposure: 1.0,
+            ..OutputTransform::default()
+        });
+        assert_eq!(*image.get_pixel(0, 0), Rgb([188, 255, 0]));
+
+        // the brightest channel is the white point of extended reinhard
+        let image = matrix.to_image(&OutputTransform {
+            tone_mapper: ToneMapper::ExtendedReinhard,
+            ..OutputTransform::default()
+        });
+        assert_eq!(image.get_pixel(0, 0)[1], 255);
     }
 }
@@ -1,10 +1,13 @@
-use super::{IntersectionResult, Plane, Point, Sphere, Triangle};
+use rand::Rng;
+
+use super::{Aabb, Float, IntersectionResult, Mesh, Plane, Point, Sphere, Triangle};
 
 #[derive(Clone)]
 pub enum Entity {
     Sphere(Sphere),
     Triangle(Triangle),
     Plane(Plane),
+    Mesh(Mesh),
 }
 
 impl Entity {
@@ -13,6 +16,37 @@ impl Entity {
             Entity::Sphere(sphere) => sphere.intersect(origin, direction),
             Entity::Triangle(triangle) => triangle.intersect(origin, direction),
             Entity::Plane(plane) => plane.intersect(origin, direction),
+            Entity::Mesh(mesh) => mesh.intersect(origin, direction),
+        }
+    }
+
+    // None means the entity is unbounded and can not be put into the bvh
+    pub fn bounds(&self) -> Option<Aabb> {
+        match self {
+            Entity::Sphere(sphere) => Some(sphere.bounds()),
+            Entity::Triangle(triangle) => Some(triangle.bounds()),
+            Entity::Mesh(mesh) => Some(mesh.bounds()),
+            Entity::Plane(_) => None,
+        }
+    }
+
+    // None means the entity is infinite and can not be sampled as a light source
+    pub fn area(&self) -> Option<Float> {
+        match self {
+            Entity::Sphere(sphere) => Some(sphere.area()),
+            Entity::Triangle(triangle) => Some(triangle.area()),
+            Entity::Mesh(mesh) => Some(mesh.area()),
+            Entity::Plane(_) => None,
+        }
+    }
+
+    // uniformly distributed point on the surface and the normal there
+    pub fn sample_point<R: Rng>(&self, rng: &mut R) -> Option<(Point, Point)> {
+        match self {
+            Entity::Sphere(sphere) => Some(sphere.sample_point(rng)),
+            Entity::Triangle(triangle) => Some(triangle.sample_point(rng)),
+            Entity::Mesh(mesh) => Some(mesh.sample_point(rng)),
+            Entity::Plane(_) => None,
         }
     }
 }
@@ -0,0 +1,310 @@
+use std::sync::Arc;
+
+use rand::Rng;
+
+use super::{Aabb, Float, IntersectionResult, Point, Triangle};
+use crate::{bvh::Bvh, material::Material};
+
+pub struct MeshBuffers {
+    pub positions: Vec<Point>,
+    pub faces: Vec<[usize; 3]>,
+    // per vertex, same length as positions
+    pub normals: Option<Vec<Point>>,
+    pub uvs: Option<Vec<(Float, Float)>>,
+    // index into materials for every face, the material of the entity is used if materials is empty
+    pub face_materials: Vec<usize>,
+    pub materials: Vec<Material>,
+}
+
+impl MeshBuffers {
+    pub fn new(positions: Vec<Point>, faces: Vec<[usize; 3]>) -> Self {
+        Self {
+            positions,
+            faces,
+            normals: None,
+            uvs: None,
+            face_materials: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+}
+
+struct MeshData {
+    buffers: MeshBuffers,
+    bvh: Bvh,
+    bounds: Aabb,
+    surface: Faces,
+    // the faces of every material, empty if the mesh has no materials of its own
+    groups: Vec<Faces>,
+}
+
+// faces with their cumulative areas, used to sample points on them uniformly
+#[derive(Default)]
+struct Faces {
+    faces: Vec<usize>,
+    area_cdf: Vec<Float>,
+}
+
+impl Faces {
+    fn push(&mut self, face: usize, area: Float) {
+        self.area_cdf.push(self.area() + area);
+        self.faces.push(face);
+    }
+
+    fn area(&self) -> Float {
+        self.area_cdf.last().copied().unwrap_or(0.0)
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> Option<usize> {
+        let x = rng.gen::<Float>() * self.area();
+        let i = self
+            .area_cdf
+            .iter()
+            .position(|&value| x < value)
+            .unwrap_or(self.faces.len().checked_sub(1)?);
+        Some(self.faces[i])
+    }
+}
+
+// triangle mesh with shared vertices, cloning it does not copy the buffers
+#[derive(Clone)]
+pub struct Mesh {
+    data: Arc<MeshData>,
+}
+
+impl Mesh {
+    pub fn new(buffers: MeshBuffers) -> Result<Self, String> {
+        let vertices = buffers.positions.len();
+        if buffers.faces.is_empty() {
+            return Err("mesh has no faces".to_string());
+        }
+        if !buffers
+            .faces
+            .iter()
+            .flatten()
+            .all(|&index| index < vertices)
+        {
+            return Err("mesh face refers to a missing vertex".to_string());
+        }
+        if let Some(normals) = &buffers.normals {
+            if normals.len() != vertices {
+                return Err("mesh needs one normal per vertex".to_string());
+            }
+        }
+        if let Some(uvs) = &buffers.uvs {
+            if uvs.len() != vertices {
+                return Err("mesh needs one uv per vertex".to_string());
+            }
+        }
+        if !buffers.materials.is_empty() {
+            if buffers.face_materials.len() != buffers.faces.len() {
+                return Err("mesh needs one material index per face".to_string());
+            }
+            if !buffers
+                .face_materials
+                .iter()
+                .all(|&index| index < buffers.materials.len())
+            {
+                return Err("mesh face refers to a missing material".to_string());
+            }
+        }
+
+        let mut bounds = Aabb::empty();
+        let mut surface = Faces::default();
+        let mut groups: Vec<Faces> = buffers.materials.iter().map(|_| Faces::default()).collect();
+        let mut face_bounds = Vec::with_capacity(buffers.faces.len());
+        for (i, face) in buffers.faces.iter().enumerate() {
+            let triangle = triangle(&buffers, face);
+            bounds = bounds.union(triangle.bounds());
+            face_bounds.push((i, triangle.bounds()));
+            surface.push(i, triangle.area());
+            if !groups.is_empty() {
+                groups[buffers.face_materials[i]].push(i, triangle.area());
+            }
+        }
+
+        Ok(Self {
+            data: Arc::new(MeshData {
+                bvh: Bvh::new(face_bounds),
+                buffers,
+                bounds,
+                surface,
+                groups,
+            }),
+        })
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.data.bounds
+    }
+
+    pub fn area(&self) -> Float {
+        self.data.surface.area()
+    }
+
+    pub fn sample_point<R: Rng>(&self, rng: &mut R) -> (Point, Point) {
+        self.sample_faces(&self.data.surface, rng)
+            .expect("meshes have faces")
+    }
+
+    // the materials of the faces, empty if the material of the entity is used
+    pub fn materials(&self) -> &[Material] {
+        &self.data.buffers.materials
+    }
+
+    // area of the faces with the material at index in materials
+    pub fn material_area(&self, material: usize) -> Float {
+        self.data.groups[material].area()
+    }
+
+    // uniformly distributed point on the faces with the material at index in materials
+    pub fn sample_material_point<R: Rng>(
+        &self,
+        material: usize,
+        rng: &mut R,
+    ) -> Option<(Point, Point)> {
+        self.sample_faces(&self.data.groups[material], rng)
+    }
+
+    fn sample_faces<R: Rng>(&self, faces: &Faces, rng: &mut R) -> Option<(Point, Point)> {
+        let face = faces.sample(rng)?;
+        Some(triangle(&self.data.buffers, &self.data.buffers.faces[face]).sample_point(rng))
+    }
+
+    pub fn intersect(&self, origin: Point, direction: Point) -> Option<IntersectionResult> {
+        let buffers = &self.data.buffers;
+        let mut closest: Option<(usize, IntersectionResult)> = None;
+        self.data
+            .bvh
+            .traverse(origin, direction, Float::INFINITY, |face| {
+                let intersection =
+                    triangle(buffers, &buffers.faces[face]).intersect(origin, direction)?;
+                let closer = match &closest {
+                    None => true,
+                    Some((closest_face, closest)) => {
+                        intersection.distance < closest.distance
+                            || (intersection.distance == closest.distance && face < *closest_face)
+                    }
+                };
+                if closer {
+                    let distance = intersection.distance;
+                    closest = Some((face, intersection));
+                    Some(distance)
+                } else {
+                    None
+                }
+            });
+        let (face, mut intersection) = closest?;
+
+        let [i0, i1, i2] = buffers.faces[face];
+        let (u, v) = intersection.barycentric.unwrap_or((0.0, 0.0));
+        let w = 1.0 - u - v;
+        if let Some(normals) = &buffers.normals {
+            let smooth = normals[i0] * w + normals[i1] * u + normals[i2] * v;
+            if smooth.len() > 0.000001 {
+                // keep the orientation of the face normal, vertex normals only bend it
+                let smooth = smooth.normalize();
+                intersection.normal = if smooth * intersection.normal < 0.0 {
+                    smooth * -1.0
+                } else {
+                    smooth
+                };
+            }
+        }
+        if !buffers.materials.is_empty() {
+            intersection = intersection.with_material(buffers.face_materials[face]);
+        }
+        Some(intersection)
+    }
+}
+
+fn triangle(buffers: &MeshBuffers, face: &[usize; 3]) -> Triangle {
+    let triangle = Triangle::new(
+        buffers.positions[face[0]],
+        buffers.positions[face[1]],
+        buffers.positions[face[2]],
+    );
+    match &buffers.uvs {
+        None => triangle,
+        Some(uvs) => triangle.with_uvs([uvs[face[0]], uvs[face[1]], uvs[face[2]]]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::Color;
+
+    // unit square in the z = 1 plane split into two faces with different materials
+    fn square() -> MeshBuffers {
+        let mut buffers = MeshBuffers::new(
+            vec![
+                Point::new(0.0, 0.0, 1.0),
+                Point::new(1.0, 0.0, 1.0),
+                Point::new(1.0, 1.0, 1.0),
+                Point::new(0.0, 1.0, 1.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        );
+        buffers.uvs = Some(vec![(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]);
+        buffers.materials = vec![
+            Material::new_light(Color::WHITE, 1.0),
+            Material::new_light(Color::WHITE, 2.0),
+        ];
+        buffers.face_materials = vec![0, 1];
+        buffers
+    }
+
+    #[test]
+    fn hits_report_face_material_and_uv() {
+        let mesh = Mesh::new(square()).unwrap();
+        let direction = Point::new(0.0, 0.0, 1.0);
+
+        let hit = mesh
+            .intersect(Point::new(0.75, 0.25, 0.0), direction)
+            .unwrap();
+        assert!((hit.distance - 1.0).abs() < 1e-9);
+        assert_eq!(hit.material, Some(0));
+        let (u, v) = hit.uv.unwrap();
+        assert!((u - 0.75).abs() < 1e-9 && (v - 0.25).abs() < 1e-9);
+
+        let hit = mesh
+            .intersect(Point::new(0.25, 0.75, 0.0), direction)
+            .unwrap();
+        assert_eq!(hit.material, Some(1));
+
+        assert!(mesh
+            .intersect(Point::new(1.5, 0.5, 0.0), direction)
+            .is_none());
+    }
+
+    #[test]
+    fn vertex_normals_are_interpolated() {
+        let mut buffers = square();
+        buffers.normals = Some(vec![
+            Point::new(-1.0, 0.0, 1.0).normalize(),
+            Point::new(1.0, 0.0, 1.0).normalize(),
+            Point::new(1.0, 0.0, 1.0).normalize(),
+            Point::new(-1.0, 0.0, 1.0).normalize(),
+        ]);
+        let mesh = Mesh::new(buffers).unwrap();
+        let direction = Point::new(0.0, 0.0, 1.0);
+        let middle = mesh
+            .intersect(Point::new(0.5, 0.1, 0.0), direction)
+            .unwrap();
+        assert!(middle.normal.x.abs() < 1e-9);
+        let right = mesh
+            .intersect(Point::new(0.9, 0.1, 0.0), direction)
+            .unwrap();
+        assert!(right.normal.x > 0.0 && right.normal.z > 0.0);
+        assert!((right.normal.len() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn invalid_buffers_are_rejected() {
+        let mut buffers = square();
+        buffers.faces.push([0, 1, 4]);
+        buffers.face_materials.push(0);
+        assert!(Mesh::new(buffers).is_err());
+    }
+}
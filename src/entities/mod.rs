@@ -1,11 +1,16 @@
 mod entity;
+mod mesh;
 mod plane;
 mod sphere;
 mod triangle;
 
-use super::{geometry::Point, Float};
+use super::{
+    geometry::{Aabb, Point},
+    Float,
+};
 
 pub use entity::Entity;
+pub use mesh::{Mesh, MeshBuffers};
 pub use plane::Plane;
 pub use sphere::Sphere;
 pub use triangle::Triangle;
@@ -14,6 +19,14 @@ pub struct IntersectionResult {
     pub intersection_point: Point,
     pub distance: Float,
     pub normal: Point,
+    // barycentric coordinates of the hit along the second and the third vertex, triangles only
+    pub barycentric: Option<(Float, Float)>,
+    // texture coordinates, if the entity has them
+    pub uv: Option<(Float, Float)>,
+    // derivatives of the point along the texture coordinates, they orient bump and normal maps
+    pub tangents: Option<(Point, Point)>,
+    // entities with several materials report the index of the one that was hit
+    pub material: Option<usize>,
 }
 
 impl IntersectionResult {
@@ -22,6 +35,30 @@ impl IntersectionResult {
             intersection_point,
             distance,
             normal,
+            barycentric: None,
+            uv: None,
+            tangents: None,
+            material: None,
         }
     }
+
+    pub fn with_barycentric(mut self, u: Float, v: Float) -> Self {
+        self.barycentric = Some((u, v));
+        self
+    }
+
+    pub fn with_uv(mut self, u: Float, v: Float) -> Self {
+        self.uv = Some((u, v));
+        self
+    }
+
+    pub fn with_tangents(mut self, dpdu: Point, dpdv: Point) -> Self {
+        self.tangents = Some((dpdu, dpdv));
+        self
+    }
+
+    pub fn with_material(mut self, material: usize) -> Self {
+        self.material = Some(material);
+        self
+    }
 }
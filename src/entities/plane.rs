@@ -16,28 +16,22 @@ impl Plane {
         }
     }
 
-    fn normal(&self, u: Float, v: Float) -> Point {
-        self.u
-            .dot(self.v)
-            .rotate(Point::new(
-                u.sin() * (v * 0.5).cos() * 0.5,
-                (u * 0.2).sin() + v.sin() * 0.7,
-                u.cos() * v.cos() * 0.1,
-            ))
-            .normalize()
+    fn normal(&self) -> Point {
+        self.u.dot(self.v).normalize()
     }
 
     pub fn intersect(&self, origin: Point, direction: Point) -> Option<IntersectionResult> {
-        let intersection_point = match self.intersect_plane(origin, direction) {
-            None => return None,
-            Some(point) => point,
-        };
+        let intersection_point = self.intersect_plane(origin, direction)?;
         let (u, v) = self.plane_coordinates(intersection_point);
-        Some(IntersectionResult::new(
-            intersection_point,
-            (intersection_point - origin).len(),
-            self.normal(u, v),
-        ))
+        Some(
+            IntersectionResult::new(
+                intersection_point,
+                (intersection_point - origin).len(),
+                self.normal(),
+            )
+            .with_uv(u, v)
+            .with_tangents(self.u, self.v),
+        )
     }
 
     fn intersect_plane(&self, origin: Point, direction: Point) -> Option<Point> {
@@ -56,19 +50,54 @@ impl Plane {
         Some(intersection_point)
     }
 
+    // coordinates of the point along the two edges of the plane, they are also its texture
+    // coordinates
     fn plane_coordinates(&self, point: Point) -> (Float, Float) {
         let point = point - self.origin;
+        let normal = self.normal();
 
-        let det = Self::det(self.u, self.v);
+        let det = Self::det(self.u, self.v, normal);
 
-        let point_u = Self::det(point, self.v) / det;
+        let point_u = Self::det(point, self.v, normal) / det;
 
-        let point_v = Self::det(self.u, point) / det;
+        let point_v = Self::det(self.u, point, normal) / det;
 
         (point_u, point_v)
     }
 
-    fn det(a: Point, b: Point) -> Float {
-        a.dot(b).sum()
+    // signed area of the parallelogram of a and b seen along the normal
+    fn det(a: Point, b: Point, normal: Point) -> Float {
+        a.dot(b) * normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coordinates_follow_the_edges() {
+        // the components of the normal sum to zero
+        let plane = Plane::new(
+            Point::new(1.0, 2.0, 3.0),
+            Point::new(3.0, 2.0, 3.0),
+            Point::new(1.0, 3.0, 4.0),
+        );
+        let point = Point::new(1.0, 2.0, 3.0)
+            + Point::new(2.0, 0.0, 0.0) * 0.25
+            + Point::new(0.0, 1.0, 1.0) * 3.0;
+        let hit = plane
+            .intersect(
+                point - Point::new(0.0, 1.0, -1.0),
+                Point::new(0.0, 1.0, -1.0),
+            )
+            .unwrap();
+        let (u, v) = hit.uv.unwrap();
+        assert!(
+            (u - 0.25).abs() < 1e-9 && (v - 3.0).abs() < 1e-9,
+            "{} {}",
+            u,
+            v
+        );
     }
 }
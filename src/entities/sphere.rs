@@ -1,4 +1,6 @@
-use super::{Float, IntersectionResult, Point};
+use rand::Rng;
+
+use super::{Aabb, Float, IntersectionResult, Point};
 
 #[derive(Clone)]
 pub struct Sphere {
@@ -8,10 +10,7 @@ pub struct Sphere {
 
 impl Sphere {
     pub fn new(origin: Point, radius: Float) -> Self {
-        Sphere {
-            origin,
-            radius: radius,
-        }
+        Sphere { origin, radius }
     }
 
     pub fn new_room(origin: Point, radius: Float) -> Self {
@@ -28,6 +27,51 @@ impl Sphere {
             (point - self.origin).normalize()
         }
     }
+
+    // longitude and latitude, u goes around the y axis and v from the lower pole at 0 to the
+    // upper one at 1
+    fn uv(&self, point: Point) -> (Float, Float) {
+        let direction = (point - self.origin) / self.radius.abs();
+        let u = 0.5 + direction.z.atan2(direction.x) / (2.0 * std::f64::consts::PI);
+        let v = 0.5 + direction.y.clamp(-1.0, 1.0).asin() / std::f64::consts::PI;
+        (u, v)
+    }
+
+    // derivatives of the point along u and v, at the poles u has none
+    fn tangents(&self, point: Point) -> (Point, Point) {
+        let offset = point - self.origin;
+        let around = (offset.x * offset.x + offset.z * offset.z).sqrt();
+        let dpdu = Point::new(-offset.z, 0.0, offset.x) * (2.0 * std::f64::consts::PI);
+        let dpdv = if around < 1e-9 {
+            Point::new(self.radius.abs(), 0.0, 0.0)
+        } else {
+            Point::new(
+                -offset.x * offset.y / around,
+                around,
+                -offset.z * offset.y / around,
+            )
+        } * std::f64::consts::PI;
+        (dpdu, dpdv)
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let radius = self.radius.abs();
+        let extent = Point::new(radius, radius, radius);
+        Aabb::new(self.origin - extent, self.origin + extent)
+    }
+
+    pub fn area(&self) -> Float {
+        4.0 * std::f64::consts::PI * self.radius * self.radius
+    }
+
+    pub fn sample_point<R: Rng>(&self, rng: &mut R) -> (Point, Point) {
+        let z = 1.0 - 2.0 * rng.gen::<Float>();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * rng.gen::<Float>();
+        let direction = Point::new(r * phi.cos(), r * phi.sin(), z);
+        let point = self.origin + direction * self.radius.abs();
+        (point, self.normal(point))
+    }
 }
 
 impl Sphere {
@@ -55,11 +99,13 @@ impl Sphere {
         }
         let delta = direction * root;
         let point = origin + delta;
-        Some(IntersectionResult::new(
-            point,
-            delta.len(),
-            self.normal(point),
-        ))
+        let (u, v) = self.uv(point);
+        let (dpdu, dpdv) = self.tangents(point);
+        Some(
+            IntersectionResult::new(point, delta.len(), self.normal(point))
+                .with_uv(u, v)
+                .with_tangents(dpdu, dpdv),
+        )
     }
 }
 
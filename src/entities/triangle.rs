@@ -1,10 +1,14 @@
-use super::{Float, IntersectionResult, Point};
+use rand::Rng;
+
+use super::{Aabb, Float, IntersectionResult, Point};
 
 #[derive(Clone)]
 pub struct Triangle {
     origin: Point,
     u: Point,
     v: Point,
+    // texture coordinates of the three points, the barycentric coordinates are used without them
+    uvs: Option<[(Float, Float); 3]>,
 }
 
 impl Triangle {
@@ -13,9 +17,15 @@ impl Triangle {
             origin: p1,
             u: p2 - p1,
             v: p3 - p1,
+            uvs: None,
         }
     }
 
+    pub fn with_uvs(mut self, uvs: [(Float, Float); 3]) -> Self {
+        self.uvs = Some(uvs);
+        self
+    }
+
     pub fn new_room(p1: Point, p2: Point, p3: Point) -> Self {
         Self::new(p1, p3, p2)
     }
@@ -24,82 +34,218 @@ impl Triangle {
         self.u.dot(self.v).normalize()
     }
 
+    pub fn area(&self) -> Float {
+        self.u.dot(self.v).len() / 2.0
+    }
+
+    pub fn sample_point<R: Rng>(&self, rng: &mut R) -> (Point, Point) {
+        let r1 = rng.gen::<Float>().sqrt();
+        let r2 = rng.gen::<Float>();
+        let point = self.origin + self.u * (r1 * (1.0 - r2)) + self.v * (r1 * r2);
+        (point, self.normal())
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        let p2 = self.origin + self.u;
+        let p3 = self.origin + self.v;
+        Aabb::new(self.origin.min(p2).min(p3), self.origin.max(p2).max(p3))
+    }
+
+    // möller-trumbore: solves origin + direction * t = origin + u * b1 + v * b2 in one pass
     pub fn intersect(&self, origin: Point, direction: Point) -> Option<IntersectionResult> {
-        let intersection_point = match self.intersect_plane(origin, direction) {
-            None => return None,
-            Some(point) => point,
-        };
-        if !self.triangle_contains(intersection_point) {
+        let p = direction.dot(self.v);
+        let det = self.u * p;
+        // the ray is parallel to the triangle plane
+        if det.abs() < 0.000000000001 {
             return None;
         }
-        Some(IntersectionResult::new(
-            intersection_point,
-            (intersection_point - origin).len(),
-            self.normal(),
-        ))
-    }
-
-    // plane equation: a x + b y + c z + d = 0 <=> (a, b, c) * (x, y, z) = -d
-    // (a, b, c) * (origin + direction * t) = -d
-    // t = -((a, b, c) * origin + d) / ((a, b, c) * direction)
-    // origin + direction t = intersection_point
-    fn intersect_plane(&self, origin: Point, direction: Point) -> Option<Point> {
-        let abc = self.u.dot(self.v);
-        let d = -(abc * self.origin);
-        let coefficient = abc * direction;
-        if coefficient.abs() < 0.00000001 {
+        let inv_det = 1.0 / det;
+
+        let s = origin - self.origin;
+        let b1 = (s * p) * inv_det;
+        if !(0.0..=1.0).contains(&b1) {
             return None;
         }
-        let free = -(abc * origin + d);
-        let t = free / coefficient;
+
+        let q = s.dot(self.u);
+        let b2 = (direction * q) * inv_det;
+        if b2 < 0.0 || b1 + b2 > 1.0 {
+            return None;
+        }
+
+        let t = (self.v * q) * inv_det;
         if t < 0.0 {
             return None;
         }
+
         let intersection_point = origin + direction * t;
-        Some(intersection_point)
+        let intersection = IntersectionResult::new(
+            intersection_point,
+            (intersection_point - origin).len(),
+            self.normal(),
+        )
+        .with_barycentric(b1, b2);
+        Some(match self.uvs {
+            None => intersection.with_uv(b1, b2).with_tangents(self.u, self.v),
+            Some([uv0, uv1, uv2]) => {
+                let b0 = 1.0 - b1 - b2;
+                let intersection = intersection.with_uv(
+                    uv0.0 * b0 + uv1.0 * b1 + uv2.0 * b2,
+                    uv0.1 * b0 + uv1.1 * b1 + uv2.1 * b2,
+                );
+                match self.tangents(uv0, uv1, uv2) {
+                    None => intersection,
+                    Some((dpdu, dpdv)) => intersection.with_tangents(dpdu, dpdv),
+                }
+            }
+        })
     }
 
-    // we find point coordinates in coordinate system with basis (u, v)
-    // if u > 0, v > 0 and u + v < 1, then point is inside of the triangle
-    #[allow(dead_code)]
-    fn triangle_contains(&self, point: Point) -> bool {
-        let point = point - self.origin;
+    // solves edge = dpdu * du + dpdv * dv for both edges, none if the texture coordinates of the
+    // points are on a line
+    fn tangents(
+        &self,
+        uv0: (Float, Float),
+        uv1: (Float, Float),
+        uv2: (Float, Float),
+    ) -> Option<(Point, Point)> {
+        let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+        let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+        let det = du1 * dv2 - dv1 * du2;
+        if det.abs() < 0.000000000001 {
+            return None;
+        }
+        let dpdu = (self.u * dv2 - self.v * dv1) / det;
+        let dpdv = (self.v * du1 - self.u * du2) / det;
+        Some((dpdu, dpdv))
+    }
+}
 
-        let det = Self::det(self.u, self.v);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let point_u = Self::det(point, self.v) / det;
-        if point_u < 0.0 {
-            return false;
-        }
+    fn triangle() -> Triangle {
+        Triangle::new(
+            Point::new(0.0, 0.0, 5.0),
+            Point::new(2.0, 0.0, 5.0),
+            Point::new(0.0, 2.0, 5.0),
+        )
+    }
 
-        let point_v = Self::det(self.u, point) / det;
-        if point_v < 0.0 || point_v + point_u > 1.0 {
-            return false;
-        }
+    fn hit_from_above(triangle: &Triangle, x: Float, y: Float) -> Option<IntersectionResult> {
+        triangle.intersect(Point::new(x, y, 0.0), Point::new(0.0, 0.0, 1.0))
+    }
+
+    fn assert_close(actual: Float, expected: Float) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
 
-        true
+    #[test]
+    fn interior_hit_has_barycentric_coordinates() {
+        let hit = hit_from_above(&triangle(), 0.5, 0.25).unwrap();
+        let (u, v) = hit.barycentric.unwrap();
+        assert_close(u, 0.25);
+        assert_close(v, 0.125);
+        assert_close(hit.distance, 5.0);
     }
 
-    fn det(a: Point, b: Point) -> Float {
-        a.dot(b).sum()
+    #[test]
+    fn edge_hits() {
+        let triangle = triangle();
+        let (u, v) = hit_from_above(&triangle, 1.0, 0.0)
+            .unwrap()
+            .barycentric
+            .unwrap();
+        assert_close(u, 0.5);
+        assert_close(v, 0.0);
+        let (u, v) = hit_from_above(&triangle, 1.0, 1.0)
+            .unwrap()
+            .barycentric
+            .unwrap();
+        assert_close(u + v, 1.0);
+        assert!(hit_from_above(&triangle, 1.0, -0.000001).is_none());
+        assert!(hit_from_above(&triangle, 1.000001, 1.0).is_none());
     }
 
-    #[allow(dead_code)]
-    fn triangle_contains_2(&self, point: Point) -> bool {
-        let point = point - self.origin;
-        Self::in_angle(Point::new(0.0, 0.0, 0.0), self.u, self.v, point)
-            && Self::in_angle(self.u, self.v, Point::new(0.0, 0.0, 0.0), point)
-            && Self::in_angle(self.v, Point::new(0.0, 0.0, 0.0), self.u, point)
+    #[test]
+    fn vertex_hits() {
+        let triangle = triangle();
+        for &(x, y, u, v) in [
+            (0.0, 0.0, 0.0, 0.0),
+            (2.0, 0.0, 1.0, 0.0),
+            (0.0, 2.0, 0.0, 1.0),
+        ]
+        .iter()
+        {
+            let (hit_u, hit_v) = hit_from_above(&triangle, x, y)
+                .unwrap()
+                .barycentric
+                .unwrap();
+            assert_close(hit_u, u);
+            assert_close(hit_v, v);
+        }
     }
 
-    fn in_angle(left: Point, middle: Point, right: Point, point: Point) -> bool {
-        let left_vec = left - middle;
-        let right_vec = right - middle;
-        let point_vec = point - middle;
+    #[test]
+    fn texture_coordinates() {
+        // without uvs the barycentric coordinates are used
+        let hit = hit_from_above(&triangle(), 0.5, 0.25).unwrap();
+        assert_eq!(hit.uv, hit.barycentric);
+
+        let triangle = triangle().with_uvs([(0.5, 0.5), (1.0, 0.5), (0.5, 0.0)]);
+        let (u, v) = hit_from_above(&triangle, 0.5, 0.25).unwrap().uv.unwrap();
+        assert_close(u, 0.625);
+        assert_close(v, 0.4375);
+    }
 
-        let right_angle_cos = right_vec * left_vec / right_vec.len();
-        let point_angle_cos = point_vec * left_vec / point_vec.len();
+    #[test]
+    fn grazing_rays() {
+        let triangle = triangle();
+        // lies in the plane of the triangle
+        assert!(triangle
+            .intersect(Point::new(-1.0, 0.5, 5.0), Point::new(1.0, 0.0, 0.0))
+            .is_none());
+        // almost parallel to the plane, but still crosses the triangle
+        let hit = triangle
+            .intersect(
+                Point::new(-999.0, 0.5, 4.999),
+                Point::new(1.0, 0.0, 0.000001),
+            )
+            .unwrap();
+        let (u, v) = hit.barycentric.unwrap();
+        assert_close(hit.intersection_point.z, 5.0);
+        assert!((u - 0.5).abs() < 1e-6);
+        assert_close(v, 0.25);
+    }
 
-        return point_angle_cos >= right_angle_cos - 0.000001;
+    #[test]
+    fn orientation_does_not_matter() {
+        // the normal (1, -1, 0) has zero component sum, which used to break the inside test
+        let triangle = Triangle::new(
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 1.0, 0.0),
+            Point::new(0.0, 0.0, 1.0),
+        );
+        let hit = triangle.intersect(Point::new(1.0, -1.0, 0.25), Point::new(-1.0, 1.0, 0.0));
+        let (u, v) = hit.unwrap().barycentric.unwrap();
+        assert_close(u, 0.0);
+        assert_close(v, 0.25);
+        assert!(triangle
+            .intersect(Point::new(3.0, 1.0, 0.25), Point::new(-1.0, 1.0, 0.0))
+            .is_none());
+        let reversed = Triangle::new_room(
+            Point::new(0.0, 0.0, 0.0),
+            Point::new(1.0, 1.0, 0.0),
+            Point::new(0.0, 0.0, 1.0),
+        );
+        assert!(reversed
+            .intersect(Point::new(1.0, -1.0, 0.25), Point::new(-1.0, 1.0, 0.0))
+            .is_some());
     }
 }
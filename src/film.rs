@@ -0,0 +1,326 @@
+use std::{
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use image::{
+    codecs::hdr::{HdrDecoder, HdrEncoder},
+    ImageError, Rgb,
+};
+
+use crate::{
+    drawing::{Color, ColorMatrix, OutputTransform},
+    Float,
+};
+
+// linear radiance, unlike the 8 bit images nothing is clamped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HdrFormat {
+    Exr,
+    Pfm,
+    Radiance,
+}
+
+impl HdrFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "exr" => Some(HdrFormat::Exr),
+            "pfm" => Some(HdrFormat::Pfm),
+            "hdr" => Some(HdrFormat::Radiance),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum FilmError {
+    Io(io::Error),
+    Exr(exr::error::Error),
+    Image(ImageError),
+    Format(String),
+}
+
+impl fmt::Display for FilmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FilmError::Io(error) => write!(f, "{}", error),
+            FilmError::Exr(error) => write!(f, "{}", error),
+            FilmError::Image(error) => write!(f, "{}", error),
+            FilmError::Format(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<io::Error> for FilmError {
+    fn from(error: io::Error) -> Self {
+        FilmError::Io(error)
+    }
+}
+
+impl From<exr::error::Error> for FilmError {
+    fn from(error: exr::error::Error) -> Self {
+        FilmError::Exr(error)
+    }
+}
+
+impl From<ImageError> for FilmError {
+    fn from(error: ImageError) -> Self {
+        FilmError::Image(error)
+    }
+}
+
+// the format is chosen by the extension, anything that is not hdr goes through to_image,
+// hdr files ignore the transform and keep the linear values
+pub fn save<P: AsRef<Path>>(
+    matrix: &ColorMatrix,
+    path: P,
+    transform: &OutputTransform,
+) -> Result<(), FilmError> {
+    let path = path.as_ref();
+    match HdrFormat::from_path(path) {
+        Some(HdrFormat::Exr) => save_exr(matrix, path),
+        Some(HdrFormat::Pfm) => save_pfm(matrix, path),
+        Some(HdrFormat::Radiance) => save_radiance(matrix, path),
+        None => Ok(matrix.to_image(transform).save(path)?),
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<ColorMatrix, FilmError> {
+    let path = path.as_ref();
+    match HdrFormat::from_path(path) {
+        Some(HdrFormat::Exr) => load_exr(path),
+        Some(HdrFormat::Pfm) => load_pfm(path),
+        Some(HdrFormat::Radiance) => load_radiance(path),
+        None => Err(FilmError::Format(format!(
+            "'{}' is not an exr, pfm or hdr file",
+            path.display()
+        ))),
+    }
+}
+
+fn rgb(color: Color) -> [f32; 3] {
+    let [r, g, b] = color.channels();
+    [r as f32, g as f32, b as f32]
+}
+
+fn from_rgb([r, g, b]: [f32; 3]) -> Color {
+    Color::new(r as Float, g as Float, b as Float)
+}
+
+fn save_exr(matrix: &ColorMatrix, path: &Path) -> Result<(), FilmError> {
+    exr::prelude::write_rgb_file(path, matrix.width(), matrix.height(), |x, y| {
+        let [r, g, b] = rgb(matrix.get(x, y));
+        (r, g, b)
+    })?;
+    Ok(())
+}
+
+fn load_exr(path: &Path) -> Result<ColorMatrix, FilmError> {
+    let image = exr::prelude::read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| ColorMatrix::new(resolution.width(), resolution.height()),
+        |matrix, position, (r, g, b, _): (f32, f32, f32, f32)| {
+            matrix.set(position.x(), position.y(), from_rgb([r, g, b]))
+        },
+    )?;
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+// portable float map: text header, then little endian floats with the bottom row first
+fn save_pfm(matrix: &ColorMatrix, path: &Path) -> Result<(), FilmError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write!(writer, "PF\n{} {}\n-1.0\n", matrix.width(), matrix.height())?;
+    for y in (0..matrix.height()).rev() {
+        for x in 0..matrix.width() {
+            for channel in rgb(matrix.get(x, y)).iter() {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn load_pfm(path: &Path) -> Result<ColorMatrix, FilmError> {
+    let bytes = fs::read(path)?;
+    let invalid = |message: &str| FilmError::Format(format!("{}: {}", path.display(), message));
+
+    // magic, width, height and scale, each followed by a single whitespace byte
+    let mut header = Vec::new();
+    let mut position = 0;
+    while header.len() < 4 {
+        let start = position;
+        while position < bytes.len() && !bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if position == start || position == bytes.len() {
+            return Err(invalid("truncated header"));
+        }
+        header.push(String::from_utf8_lossy(&bytes[start..position]).to_string());
+        position += 1;
+        while header.len() < 4 && position < bytes.len() && bytes[position].is_ascii_whitespace() {
+            position += 1;
+        }
+    }
+
+    let channels = match header[0].as_str() {
+        "PF" => 3,
+        "Pf" => 1,
+        _ => return Err(invalid("not a portable float map")),
+    };
+    let width: usize = header[1].parse().map_err(|_| invalid("invalid width"))?;
+    let height: usize = header[2].parse().map_err(|_| invalid("invalid height"))?;
+    let scale: f32 = header[3].parse().map_err(|_| invalid("invalid scale"))?;
+    if width == 0 || height == 0 {
+        return Err(invalid("empty image"));
+    }
+    // sizes from the header must not overflow before they are compared with the file
+    let size = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels * 4))
+        .ok_or_else(|| invalid("image too large"))?;
+    if bytes.len() - position < size {
+        return Err(invalid("truncated pixel data"));
+    }
+
+    // a negative scale means little endian
+    let float = |index: usize| {
+        let offset = position + index * 4;
+        let value = [
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ];
+        if scale < 0.0 {
+            f32::from_le_bytes(value)
+        } else {
+            f32::from_be_bytes(value)
+        }
+    };
+    let mut matrix = ColorMatrix::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = ((height - 1 - y) * width + x) * channels;
+            let color = if channels == 3 {
+                [float(pixel), float(pixel + 1), float(pixel + 2)]
+            } else {
+                [float(pixel); 3]
+            };
+            matrix.set(x, y, from_rgb(color));
+        }
+    }
+    Ok(matrix)
+}
+
+// radiance rgbe, 8 bit mantissas with a shared exponent, negative values become 0
+fn save_radiance(matrix: &ColorMatrix, path: &Path) -> Result<(), FilmError> {
+    let mut pixels = Vec::with_capacity(matrix.width() * matrix.height());
+    for y in 0..matrix.height() {
+        for x in 0..matrix.width() {
+            let [r, g, b] = rgb(matrix.get(x, y));
+            pixels.push(Rgb([r.max(0.0), g.max(0.0), b.max(0.0)]));
+        }
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    HdrEncoder::new(&mut writer).encode(&pixels, matrix.width(), matrix.height())?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn load_radiance(path: &Path) -> Result<ColorMatrix, FilmError> {
+    let decoder = HdrDecoder::new(BufReader::new(File::open(path)?))?;
+    let metadata = decoder.metadata();
+    let (width, height) = (metadata.width as usize, metadata.height as usize);
+    let pixels = decoder.read_image_hdr()?;
+    let mut matrix = ColorMatrix::new(width, height);
+    for (i, pixel) in pixels.iter().enumerate() {
+        matrix.set(i % width, i / width, from_rgb(pixel.0));
+    }
+    Ok(matrix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient() -> ColorMatrix {
+        let mut matrix = ColorMatrix::new(7, 5);
+        for y in 0..5 {
+            for x in 0..7 {
+                let value = (x + 1) as Float * 0.37 + y as Float * 3.0;
+                matrix.set(x, y, Color::new(value, value * 0.5, value * 2.0));
+            }
+        }
+        matrix
+    }
+
+    fn round_trip(extension: &str, tolerance: Float) {
+        let path = std::env::temp_dir().join(format!(
+            "ray-tracing-film-{}.{}",
+            std::process::id(),
+            extension
+        ));
+        let matrix = gradient();
+        save(&matrix, &path, &OutputTransform::default()).unwrap();
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.width(), matrix.width());
+        assert_eq!(loaded.height(), matrix.height());
+        for y in 0..matrix.height() {
+            for x in 0..matrix.width() {
+                let expected = matrix.get(x, y).channels();
+                let actual = loaded.get(x, y).channels();
+                for channel in 0..3 {
+                    let error = (actual[channel] - expected[channel]).abs() / expected[channel];
+                    assert!(
+                        error < tolerance,
+                        "{} at ({}, {}): {:?} != {:?}",
+                        extension,
+                        x,
+                        y,
+                        actual,
+                        expected
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        round_trip("exr", 1e-6);
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        round_trip("pfm", 1e-6);
+    }
+
+    #[test]
+    fn pfm_sizes_are_checked_against_the_file() {
+        let path =
+            std::env::temp_dir().join(format!("ray-tracing-film-size-{}.pfm", std::process::id()));
+        for (header, message) in [
+            ("PF\n18446744073709551615 2\n-1.0\n", "image too large"),
+            ("PF\n4 4\n-1.0\n", "truncated pixel data"),
+        ]
+        .iter()
+        {
+            fs::write(&path, format!("{}{}", header, "\0".repeat(12))).unwrap();
+            let error = load(&path).err().unwrap().to_string();
+            assert!(error.ends_with(message), "{}", error);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn radiance_round_trip() {
+        // rgbe only keeps 8 bits of mantissa shared by all channels
+        round_trip("hdr", 0.04);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    drawing::{Color, ColorMatrix},
+    Float,
+};
+
+// pixel reconstruction filters, separable and measured in pixels from the pixel center
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    // mitchell-netravali with b = c = 1/3
+    Mitchell,
+}
+
+impl Filter {
+    pub fn radius(self) -> Float {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    pub fn weight(self, dx: Float, dy: Float) -> Float {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(self, x: Float) -> Float {
+        let x = x.abs();
+        let radius = self.radius();
+        if x >= radius {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - x,
+            Filter::Gaussian => {
+                // shifted down so that it reaches 0 at the radius
+                let alpha = 2.0;
+                (-alpha * x * x).exp() - (-alpha * radius * radius).exp()
+            }
+            Filter::Mitchell => {
+                let (b, c) = (1.0 / 3.0, 1.0 / 3.0);
+                let value = if x < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c)
+                };
+                value / 6.0
+            }
+        }
+    }
+}
+
+// weighted sum of the samples around every pixel, samples near a pixel border also land in
+// the neighbouring pixels if the filter is wider than half a pixel
+pub struct FilteredImage {
+    // first pixel covered by this image
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    filter: Filter,
+    sum: ColorMatrix,
+    weights: Vec<Float>,
+}
+
+impl FilteredImage {
+    pub fn new(width: usize, height: usize, filter: Filter) -> Self {
+        Self::region(0, 0, width, height, filter)
+    }
+
+    // covers only the pixels from (x, y) to (x + width, y + height) of a larger image,
+    // samples that fall outside of it are dropped
+    pub fn region(x: usize, y: usize, width: usize, height: usize, filter: Filter) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+            filter,
+            sum: ColorMatrix::new(width, height),
+            weights: vec![0.0; width * height],
+        }
+    }
+
+    // every pixel already holds samples of the given total weight averaging to its color in
+    // matrix, renders continue from it
+    pub fn from_matrix(matrix: &ColorMatrix, weight: Float, filter: Filter) -> Self {
+        let mut image = Self::new(matrix.width(), matrix.height(), filter);
+        for j in 0..matrix.height() {
+            for i in 0..matrix.width() {
+                image.add(i, j, matrix.get(i, j) * weight, weight);
+            }
+        }
+        image
+    }
+
+    // x and y are image coordinates, the center of pixel (i, j) is (i + 0.5, j + 0.5)
+    pub fn add_sample(&mut self, x: Float, y: Float, color: Color) {
+        let radius = self.filter.radius();
+        let from_x = ((x - radius - 0.5).ceil() as isize).max(self.x as isize);
+        let from_y = ((y - radius - 0.5).ceil() as isize).max(self.y as isize);
+        let to_x = ((x + radius - 0.5).floor() as isize).min((self.x + self.width) as isize - 1);
+        let to_y = ((y + radius - 0.5).floor() as isize).min((self.y + self.height) as isize - 1);
+        for j in from_y..=to_y {
+            for i in from_x..=to_x {
+                let weight = self
+                    .filter
+                    .weight(x - (i as Float + 0.5), y - (j as Float + 0.5));
+                if weight != 0.0 {
+                    self.add(
+                        i as usize - self.x,
+                        j as usize - self.y,
+                        color * weight,
+                        weight,
+                    );
+                }
+            }
+        }
+    }
+
+    fn add(&mut self, i: usize, j: usize, color: Color, weight: Float) {
+        self.sum.set(i, j, self.sum.get(i, j) + color);
+        self.weights[j * self.width + i] += weight;
+    }
+
+    // other has to lie inside of this image
+    pub fn merge(&mut self, other: &FilteredImage) {
+        for j in 0..other.height {
+            for i in 0..other.width {
+                self.add(
+                    other.x + i - self.x,
+                    other.y + j - self.y,
+                    other.sum.get(i, j),
+                    other.weights[j * other.width + i],
+                );
+            }
+        }
+    }
+
+    pub fn to_matrix(&self) -> ColorMatrix {
+        let mut matrix = ColorMatrix::new(self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                let weight = self.weights[j * self.width + i];
+                if weight.abs() > 1e-12 {
+                    matrix.set(i, j, self.sum.get(i, j) * (1.0 / weight));
+                }
+            }
+        }
+        matrix
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILTERS: [Filter; 4] = [
+        Filter::Box,
+        Filter::Tent,
+        Filter::Gaussian,
+        Filter::Mitchell,
+    ];
+
+    #[test]
+    fn filters_peak_at_the_center_and_vanish_at_the_radius() {
+        for &filter in FILTERS.iter() {
+            let center = filter.weight(0.0, 0.0);
+            assert!(center > 0.0, "{:?}", filter);
+            for i in 1..100 {
+                let x = i as Float * 0.03;
+                assert!(filter.weight(x, 0.0) <= center, "{:?}", filter);
+                assert_eq!(filter.weight(x, 0.0), filter.weight(-x, 0.0));
+            }
+            assert_eq!(filter.weight(filter.radius(), 0.0), 0.0, "{:?}", filter);
+        }
+        // the mitchell filter has negative lobes
+        assert!(Filter::Mitchell.weight(1.5, 0.0) < 0.0);
+    }
+
+    #[test]
+    fn constant_color_is_reconstructed_exactly() {
+        let color = Color::new(0.25, 0.5, 1.5);
+        for &filter in FILTERS.iter() {
+            let mut image = FilteredImage::new(5, 4, filter);
+            for j in 0..16 {
+                for i in 0..20 {
+                    image.add_sample((i as Float + 0.5) / 4.0, (j as Float + 0.5) / 4.0, color);
+                }
+            }
+            let matrix = image.to_matrix();
+            for j in 0..4 {
+                for i in 0..5 {
+                    let channels = matrix.get(i, j).channels();
+                    for (actual, expected) in channels.iter().zip(color.channels().iter()) {
+                        assert!((actual - expected).abs() < 1e-9, "{:?}", filter);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn box_filter_keeps_samples_in_their_pixel() {
+        let mut image = FilteredImage::new(2, 1, Filter::Box);
+        image.add_sample(0.9, 0.5, Color::WHITE);
+        image.add_sample(1.1, 0.5, Color::BLACK);
+        let matrix = image.to_matrix();
+        assert_eq!(matrix.get(0, 0).channels(), [1.0, 1.0, 1.0]);
+        assert_eq!(matrix.get(1, 0).channels(), [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn regions_merge_into_the_full_image() {
+        let mut full = FilteredImage::new(4, 4, Filter::Tent);
+        let mut expected = FilteredImage::new(4, 4, Filter::Tent);
+        let samples = [(0.2, 0.3), (1.9, 2.2), (2.5, 1.0), (3.9, 3.9)];
+        for (k, &(x, y)) in samples.iter().enumerate() {
+            let color = Color::new(k as Float, 1.0, 2.0);
+            expected.add_sample(x, y, color);
+            // a one pixel margin around the pixel that contains the sample is enough for the tent
+            let (i, j) = (x as usize, y as usize);
+            let (from_x, from_y) = (i.saturating_sub(1), j.saturating_sub(1));
+            let mut region = FilteredImage::region(
+                from_x,
+                from_y,
+                (i + 2).min(4) - from_x,
+                (j + 2).min(4) - from_y,
+                Filter::Tent,
+            );
+            region.add_sample(x, y, color);
+            full.merge(&region);
+        }
+        let (full, expected) = (full.to_matrix(), expected.to_matrix());
+        for j in 0..4 {
+            for i in 0..4 {
+                assert_eq!(full.get(i, j).channels(), expected.get(i, j).channels());
+            }
+        }
+    }
+}
@@ -26,13 +26,17 @@ impl Point {
         *self - normal * (2.0 * (normal * *self))
     }
 
-    pub fn refract(&self, normal: Point, coefficient: Float) -> Point {
-        let vec = self.normalize();
-        let n1 = vec.len();
-        let n2 = coefficient;
-        vec + normal
-            * (((n2 * n2 - n1 * n1) / (vec * normal).powi(2) + 1.0).sqrt() - 1.0)
-            * (vec * normal)
+    // snell's law, normal has to face the incoming ray and eta is the index of refraction of
+    // the incoming side divided by the one of the other side, None on total internal reflection
+    pub fn refract(&self, normal: Point, eta: Float) -> Option<Point> {
+        let direction = self.normalize();
+        let cos_i = -(direction * normal);
+        let k = 1.0 - eta * eta * (1.0 - cos_i * cos_i);
+        if k < 0.0 {
+            None
+        } else {
+            Some((direction * eta + normal * (eta * cos_i - k.sqrt())).normalize())
+        }
     }
 
     pub fn dot(self, other: Point) -> Point {
@@ -43,30 +47,40 @@ impl Point {
         )
     }
 
-    pub fn sum(self) -> Float {
-        self.x + self.y + self.z
+    pub fn min(self, other: Point) -> Point {
+        Point::new(
+            self.x.min(other.x),
+            self.y.min(other.y),
+            self.z.min(other.z),
+        )
+    }
+
+    pub fn max(self, other: Point) -> Point {
+        Point::new(
+            self.x.max(other.x),
+            self.y.max(other.y),
+            self.z.max(other.z),
+        )
     }
 
-    pub fn rotate(&self, angles: Point) -> Point {
-        let sin = Point::new(angles.x.sin(), angles.y.sin(), angles.z.sin());
-        let cos = Point::new(angles.x.cos(), angles.y.cos(), angles.z.cos());
+    // two unit vectors orthogonal to self and to each other, self must be normalized
+    pub fn orthonormal_basis(&self) -> (Point, Point) {
+        let helper = if self.x.abs() > 0.9 {
+            Point::new(0.0, 1.0, 0.0)
+        } else {
+            Point::new(1.0, 0.0, 0.0)
+        };
+        let tangent = self.dot(helper).normalize();
+        let bitangent = self.dot(tangent);
+        (tangent, bitangent)
+    }
 
-        let vec = Point::new(
-            self.x,
-            self.y * cos.x + self.z * sin.x,
-            self.y * -sin.x + self.z * cos.x,
-        );
-        let vec = Point::new(
-            vec.x * cos.y - vec.z * sin.y,
-            vec.y,
-            vec.x * sin.y + vec.z * cos.y,
-        );
-        let vec = Point::new(
-            vec.x * cos.z + vec.y * sin.z,
-            -vec.x * sin.z + vec.y * cos.z,
-            vec.z,
-        );
-        vec
+    pub fn axis(&self, axis: usize) -> Float {
+        match axis {
+            0 => self.x,
+            1 => self.y,
+            _ => self.z,
+        }
     }
 }
 
@@ -125,3 +139,61 @@ impl Div<Float> for Point {
         Point::new(self.x / other, self.y / other, self.z / other)
     }
 }
+
+// axis-aligned bounding box, used by the bvh in world
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn new(min: Point, max: Point) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self::new(
+            Point::new(Float::INFINITY, Float::INFINITY, Float::INFINITY),
+            Point::new(
+                Float::NEG_INFINITY,
+                Float::NEG_INFINITY,
+                Float::NEG_INFINITY,
+            ),
+        )
+    }
+
+    pub fn union(&self, other: Aabb) -> Aabb {
+        Aabb::new(self.min.min(other.min), self.max.max(other.max))
+    }
+
+    pub fn center(&self) -> Point {
+        (self.min + self.max) / 2.0
+    }
+
+    pub fn surface_area(&self) -> Float {
+        let size = self.max - self.min;
+        if size.x < 0.0 || size.y < 0.0 || size.z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (size.x * size.y + size.y * size.z + size.z * size.x)
+    }
+
+    // slab test, returns ray parameter of the entry point if the ray hits the box before max_t
+    pub fn intersect(&self, origin: Point, inv_direction: Point, max_t: Float) -> Option<Float> {
+        let mut t_min: Float = 0.0;
+        let mut t_max = max_t;
+        for axis in 0..3 {
+            let t1 = (self.min.axis(axis) - origin.axis(axis)) * inv_direction.axis(axis);
+            let t2 = (self.max.axis(axis) - origin.axis(axis)) * inv_direction.axis(axis);
+            // min/max ignore NaN, which appears for rays lying in a slab plane
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+        }
+        if t_min <= t_max {
+            Some(t_min)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::{drawing::Color, geometry::Point, Float};
+
+// lights that are not part of the geometry, they are never seen directly
+//
+// point and spot lights give intensity * color / distance² at a distance, directional lights
+// give intensity * color everywhere, area lights emit radiance intensity * color from both
+// sides of every point of their surface
+#[derive(Clone, Copy, Debug)]
+pub enum Light {
+    Point {
+        position: Point,
+        color: Color,
+        intensity: Float,
+    },
+    Directional {
+        // the direction the light travels in
+        direction: Point,
+        color: Color,
+        intensity: Float,
+    },
+    Spot {
+        position: Point,
+        direction: Point,
+        // full intensity inside the inner cone, none outside of the outer one, in radians
+        inner_angle: Float,
+        outer_angle: Float,
+        // exponent of the transition between the cones
+        falloff: Float,
+        color: Color,
+        intensity: Float,
+    },
+    Rectangle {
+        corner: Point,
+        u: Point,
+        v: Point,
+        color: Color,
+        intensity: Float,
+    },
+    Disk {
+        center: Point,
+        normal: Point,
+        radius: Float,
+        color: Color,
+        intensity: Float,
+    },
+    Sphere {
+        center: Point,
+        radius: Float,
+        color: Color,
+        intensity: Float,
+    },
+}
+
+// light arriving at a point from one sampled point of a light
+pub struct LightSample {
+    // unit vector from the lit point towards the light
+    pub direction: Point,
+    // infinite for directional lights
+    pub distance: Float,
+    // irradiance on a surface facing the light, divided by the pdf for area lights
+    pub irradiance: Color,
+    // the emissive entity the sample lies on, shadow rays have to reach it
+    pub entity: Option<usize>,
+}
+
+impl Light {
+    // point, directional and spot lights always give the same sample
+    pub fn is_delta(&self) -> bool {
+        matches!(
+            self,
+            Light::Point { .. } | Light::Directional { .. } | Light::Spot { .. }
+        )
+    }
+
+    pub fn sample<R: Rng>(&self, point: Point, rng: &mut R) -> Option<LightSample> {
+        match *self {
+            Light::Point {
+                position,
+                color,
+                intensity,
+            } => {
+                let (direction, distance) = towards(point, position)?;
+                Some(LightSample {
+                    direction,
+                    distance,
+                    irradiance: color * (intensity / (distance * distance)),
+                    entity: None,
+                })
+            }
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+            } => Some(LightSample {
+                direction: (direction * -1.0).normalize(),
+                distance: Float::INFINITY,
+                irradiance: color * intensity,
+                entity: None,
+            }),
+            Light::Spot {
+                position,
+                direction: axis,
+                inner_angle,
+                outer_angle,
+                falloff,
+                color,
+                intensity,
+            } => {
+                let (direction, distance) = towards(point, position)?;
+                let cos = -(direction * axis.normalize());
+                let (cos_inner, cos_outer) = (inner_angle.cos(), outer_angle.cos());
+                let cone = if cos >= cos_inner {
+                    1.0
+                } else if cos <= cos_outer {
+                    return None;
+                } else {
+                    ((cos - cos_outer) / (cos_inner - cos_outer)).powf(falloff)
+                };
+                Some(LightSample {
+                    direction,
+                    distance,
+                    irradiance: color * (intensity * cone / (distance * distance)),
+                    entity: None,
+                })
+            }
+            Light::Rectangle {
+                corner,
+                u,
+                v,
+                color,
+                intensity,
+            } => {
+                let position = corner + u * rng.gen::<Float>() + v * rng.gen::<Float>();
+                area_sample(point, position, u.dot(v), u.dot(v).len(), color * intensity)
+            }
+            Light::Disk {
+                center,
+                normal,
+                radius,
+                color,
+                intensity,
+            } => {
+                let (tangent, bitangent) = normal.normalize().orthonormal_basis();
+                let r = radius * rng.gen::<Float>().sqrt();
+                let phi = 2.0 * PI * rng.gen::<Float>();
+                let position = center + tangent * (r * phi.cos()) + bitangent * (r * phi.sin());
+                area_sample(
+                    point,
+                    position,
+                    normal,
+                    PI * radius * radius,
+                    color * intensity,
+                )
+            }
+            Light::Sphere {
+                center,
+                radius,
+                color,
+                intensity,
+            } => {
+                let z = 1.0 - 2.0 * rng.gen::<Float>();
+                let r = (1.0 - z * z).max(0.0).sqrt();
+                let phi = 2.0 * PI * rng.gen::<Float>();
+                let normal = Point::new(r * phi.cos(), r * phi.sin(), z);
+                let position = center + normal * radius;
+                // the far side of the sphere is hidden by the near one
+                if normal * (point - position) <= 0.0 {
+                    return None;
+                }
+                area_sample(
+                    point,
+                    position,
+                    normal,
+                    4.0 * PI * radius * radius,
+                    color * intensity,
+                )
+            }
+        }
+    }
+}
+
+fn towards(point: Point, position: Point) -> Option<(Point, Float)> {
+    let to_light = position - point;
+    let distance = to_light.len();
+    if distance < 0.000000001 {
+        return None;
+    }
+    Some((to_light / distance, distance))
+}
+
+// a uniformly sampled point of an area light, the pdf is 1 / area
+fn area_sample(
+    point: Point,
+    position: Point,
+    normal: Point,
+    area: Float,
+    radiance: Color,
+) -> Option<LightSample> {
+    let (direction, distance) = towards(point, position)?;
+    let cos_light = (direction * normal.normalize()).abs();
+    Some(LightSample {
+        direction,
+        distance,
+        irradiance: radiance * (cos_light * area / (distance * distance)),
+        entity: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn irradiance(light: &Light, point: Point, samples: usize) -> Float {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut total = 0.0;
+        for _ in 0..samples {
+            if let Some(sample) = light.sample(point, &mut rng) {
+                // the receiver faces straight up to the light
+                total += sample.irradiance.channels()[0] * sample.direction.z.max(0.0);
+            }
+        }
+        total / samples as Float
+    }
+
+    #[test]
+    fn point_light_falls_off_with_the_square_of_the_distance() {
+        let light = Light::Point {
+            position: Point::new(0.0, 0.0, 2.0),
+            color: Color::WHITE,
+            intensity: 8.0,
+        };
+        assert!((irradiance(&light, Point::new(0.0, 0.0, 0.0), 1) - 2.0).abs() < 1e-12);
+        assert!((irradiance(&light, Point::new(0.0, 0.0, -2.0), 1) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn spot_light_cone() {
+        let light = Light::Spot {
+            position: Point::new(0.0, 0.0, 1.0),
+            direction: Point::new(0.0, 0.0, -1.0),
+            inner_angle: 0.2,
+            outer_angle: 0.4,
+            falloff: 1.0,
+            color: Color::WHITE,
+            intensity: 1.0,
+        };
+        let mut rng = StdRng::seed_from_u64(1);
+        let at = |x: Float, rng: &mut StdRng| {
+            light
+                .sample(Point::new(x, 0.0, 0.0), rng)
+                .map(|sample| sample.irradiance.channels()[0] * (1.0 + x * x))
+        };
+        assert!((at(0.1, &mut rng).unwrap() - 1.0).abs() < 1e-12);
+        let middle = at(0.3_f64.tan(), &mut rng).unwrap();
+        assert!(middle > 0.0 && middle < 1.0);
+        assert!(at(0.5, &mut rng).is_none());
+    }
+
+    #[test]
+    fn area_lights_match_their_analytic_irradiance() {
+        // a disk of radius r at height h gives pi * L * r² / (h² + r²) on its axis
+        let disk = Light::Disk {
+            center: Point::new(0.0, 0.0, 1.0),
+            normal: Point::new(0.0, 0.0, 1.0),
+            radius: 1.0,
+            color: Color::WHITE,
+            intensity: 1.0,
+        };
+        let expected = PI / 2.0;
+        assert!((irradiance(&disk, Point::new(0.0, 0.0, 0.0), 200_000) - expected).abs() < 0.01);
+
+        // a sphere of radius r at distance d gives pi * L * r² / d²
+        let sphere = Light::Sphere {
+            center: Point::new(0.0, 0.0, 3.0),
+            radius: 1.0,
+            color: Color::WHITE,
+            intensity: 1.0,
+        };
+        let expected = PI / 9.0;
+        assert!((irradiance(&sphere, Point::new(0.0, 0.0, 0.0), 200_000) - expected).abs() < 0.01);
+
+        // a rectangle and a disk of the same area far away look the same
+        let rectangle = Light::Rectangle {
+            corner: Point::new(-0.5, -0.5, 20.0),
+            u: Point::new(1.0, 0.0, 0.0),
+            v: Point::new(0.0, 1.0, 0.0),
+            color: Color::WHITE,
+            intensity: 1.0,
+        };
+        let expected = 1.0 / 400.0;
+        let actual = irradiance(&rectangle, Point::new(0.0, 0.0, 0.0), 1000);
+        assert!((actual - expected).abs() < 1e-5);
+    }
+}
@@ -1,32 +1,129 @@
-use crate::{drawing::Color, Float};
+use std::sync::Arc;
 
-#[derive(Clone, Copy)]
+use crate::{
+    bsdf::{Bsdf, Lambert, Layered, Mirror, Mix, Rough, SmoothDielectric, Tinted},
+    bump::Bump,
+    drawing::Color,
+    geometry::Point,
+    medium::Medium,
+    microfacet::{Fresnel, Microfacet},
+    sampling,
+    texture::Texture,
+    Float,
+};
+
+#[derive(Clone)]
 pub struct Material {
+    // how the surface scatters light, shared between all entities of the material
+    pub bsdf: Arc<dyn Bsdf>,
+    // emitted radiance is color * light, textured emitters glow white
     pub color: Color,
-    pub reflection: Float,
-    pub diffuse: Float,
-    pub transparency: Float,
-    pub refraction_coefficient: Float,
     pub light: Float,
+    // transparent materials are media that rays travel through
+    pub medium: Option<Medium>,
+    // bends the normal of every entity with the material
+    pub bump: Option<Bump>,
+    // shading of the ray tracer
+    pub phong: Phong,
 }
 
 impl Material {
+    pub fn with_bsdf<B: Bsdf + 'static>(bsdf: B) -> Self {
+        Self {
+            bsdf: Arc::new(bsdf),
+            color: Color::WHITE,
+            light: 0.0,
+            medium: None,
+            bump: None,
+            phong: Phong::default(),
+        }
+    }
+
+    // reflection of the color is lambertian for diffuse 1, a mirror for diffuse 0 and a rough
+    // metal in between, transparency of the rest is smooth glass
     #[allow(dead_code)]
-    pub fn new(
-        color: Color,
-        reflection: Float,
+    pub fn new<T: Into<Texture>, R: Into<Texture>, U: Into<Texture>>(
+        color: T,
+        reflection: R,
         diffuse: Float,
-        transparency: Float,
+        transparency: U,
         refraction_coefficient: Float,
         light: Float,
     ) -> Self {
-        Self {
+        Material::new_filled(
             color,
             reflection,
             diffuse,
             transparency,
-            refraction_coefficient,
-            light: light,
+            Medium::new(refraction_coefficient),
+            light,
+        )
+    }
+
+    // like new with the glass filled with medium, light refracted into a medium that absorbs is
+    // colored by the absorption instead of by color at every boundary
+    pub fn new_filled<T: Into<Texture>, R: Into<Texture>, U: Into<Texture>>(
+        color: T,
+        reflection: R,
+        diffuse: Float,
+        transparency: U,
+        medium: Medium,
+        light: Float,
+    ) -> Self {
+        let color = color.into();
+        let reflection = reflection.into();
+        let transparency = transparency.into();
+        let surface: Arc<dyn Bsdf> = if diffuse >= 1.0 {
+            Arc::new(Lambert {
+                albedo: color.clone() * reflection,
+            })
+        } else {
+            let specular: Arc<dyn Bsdf> = if diffuse <= 0.0 {
+                Arc::new(Mirror {
+                    tint: Color::WHITE.into(),
+                })
+            } else {
+                Arc::new(Tinted {
+                    bsdf: Arc::new(Microfacet::Conductor {
+                        roughness: diffuse,
+                        fresnel: Fresnel::Schlick(Color::WHITE),
+                    }),
+                    tint: color.clone(),
+                })
+            };
+            Arc::new(Mix {
+                first: Arc::new(Lambert {
+                    albedo: color.clone(),
+                }),
+                second: specular,
+                weight: reflection,
+            })
+        };
+        let opaque = matches!(transparency.constant_scalar(), Some(t) if t <= 0.0);
+        let (bsdf, medium): (Arc<dyn Bsdf>, _) = if opaque {
+            (surface, None)
+        } else {
+            let glass = Arc::new(SmoothDielectric {
+                tint: if medium.absorbs() {
+                    Color::WHITE.into()
+                } else {
+                    color.clone()
+                },
+            });
+            let bsdf = Arc::new(Mix {
+                first: surface,
+                second: glass,
+                weight: transparency,
+            });
+            (bsdf, Some(medium))
+        };
+        Self {
+            bsdf,
+            color: color.constant().unwrap_or(Color::WHITE),
+            light,
+            medium,
+            bump: None,
+            phong: Phong::default(),
         }
     }
 
@@ -34,51 +131,325 @@ impl Material {
     pub fn new_light(color: Color, light: Float) -> Self {
         Self {
             color,
-            diffuse: 0.0,
-            reflection: 0.0,
-            transparency: 0.0,
-            refraction_coefficient: 1.0,
             light,
+            ..Material::with_bsdf(Lambert {
+                albedo: Color::BLACK.into(),
+            })
         }
     }
 
     #[allow(dead_code)]
-    pub fn new_diffuse(color: Color) -> Self {
-        Self {
-            color,
-            diffuse: 1.0,
-            reflection: 0.9,
-            transparency: 0.0,
-            refraction_coefficient: 1.0,
-            light: 0.0,
-        }
+    pub fn new_diffuse<T: Into<Texture>>(color: T) -> Self {
+        Material::new(color, 0.9, 1.0, 0.0, 1.0, 0.0)
+    }
+
+    #[allow(dead_code)]
+    pub fn new_mirror<T: Into<Texture>, R: Into<Texture>>(color: T, reflection: R) -> Self {
+        Material::new(color, reflection, 0.0, 0.0, 1.0, 0.0)
     }
 
     #[allow(dead_code)]
-    pub fn new_mirror(color: Color, reflection: Float) -> Self {
+    pub fn new_transparent<T: Into<Texture>, U: Into<Texture>>(
+        color: T,
+        transparency: U,
+        medium: Medium,
+    ) -> Self {
+        Material::new_filled(color, 0.0, 0.0, transparency, medium, 0.0)
+    }
+
+    // the fresnel reflectance gives the color, color only tints it
+    pub fn new_conductor<R: Into<Texture>>(fresnel: Fresnel, roughness: R) -> Self {
+        let conductor = Microfacet::Conductor {
+            roughness: 0.0,
+            fresnel,
+        };
         Self {
-            color,
-            reflection,
-            diffuse: 0.0,
-            transparency: 0.0,
-            refraction_coefficient: 1.0,
-            light: 0.0,
+            bsdf: rough(conductor, roughness.into()),
+            ..Material::with_bsdf(conductor)
         }
     }
 
-    #[allow(dead_code)]
-    pub fn new_transparent(
-        color: Color,
-        transparency: Float,
-        refraction_coefficient: Float,
+    pub fn new_rough_transparent<T: Into<Texture>, R: Into<Texture>>(
+        color: T,
+        roughness: R,
+        medium: Medium,
     ) -> Self {
+        let glass = Self {
+            bsdf: rough(Microfacet::Dielectric { roughness: 0.0 }, roughness.into()),
+            medium: Some(medium),
+            ..Material::with_bsdf(Microfacet::Dielectric { roughness: 0.0 })
+        };
+        // the absorption of the medium colors the light inside instead
+        if medium.absorbs() {
+            glass
+        } else {
+            glass.tinted(color)
+        }
+    }
+
+    // weight is the fraction of the light scattered by second, everything else comes from first
+    pub fn mix<T: Into<Texture>>(first: &Material, second: &Material, weight: T) -> Self {
         Self {
-            color,
-            reflection: 0.0,
-            diffuse: 0.0,
-            transparency,
-            refraction_coefficient,
-            light: 0.0,
+            bsdf: Arc::new(Mix {
+                first: first.bsdf.clone(),
+                second: second.bsdf.clone(),
+                weight: weight.into(),
+            }),
+            medium: first.medium.or(second.medium),
+            ..first.clone()
+        }
+    }
+
+    // base under a clear coat with the given roughness and index of refraction
+    pub fn coated(base: &Material, roughness: Float, refraction_coefficient: Float) -> Self {
+        Self {
+            bsdf: Arc::new(Layered::new(
+                base.bsdf.clone(),
+                roughness,
+                refraction_coefficient,
+            )),
+            ..base.clone()
+        }
+    }
+
+    // tints everything the material scatters
+    pub fn tinted<T: Into<Texture>>(self, tint: T) -> Self {
+        let tint = tint.into();
+        if tint.constant() == Some(Color::WHITE) {
+            return self;
+        }
+        Self {
+            bsdf: Arc::new(Tinted {
+                bsdf: self.bsdf.clone(),
+                tint,
+            }),
+            ..self
+        }
+    }
+
+    pub fn refraction_coefficient(&self) -> Float {
+        match self.medium {
+            None => 1.0,
+            Some(medium) => medium.refraction_coefficient,
+        }
+    }
+}
+
+// the microfacet with the roughness, only textured roughness is looked up at every hit
+fn rough(microfacet: Microfacet, roughness: Texture) -> Arc<dyn Bsdf> {
+    match roughness.constant_scalar() {
+        Some(roughness) => Arc::new(microfacet.with_roughness(roughness)),
+        None => Arc::new(Rough {
+            microfacet,
+            roughness,
+        }),
+    }
+}
+
+// the highlight of phong reflects the light around the normal and compares it with the view,
+// blinn-phong compares the normal with the halfway vector between the light and the view
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Specular {
+    Phong,
+    BlinnPhong,
+}
+
+// coefficients of the ambient, diffuse and specular terms of the ray tracer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Phong {
+    pub ka: Float,
+    pub kd: Float,
+    pub ks: Float,
+    pub shininess: Float,
+    pub specular: Specular,
+}
+
+impl Default for Phong {
+    fn default() -> Self {
+        Self {
+            ka: 1.0,
+            kd: 1.0,
+            ks: 0.3,
+            shininess: 80.0,
+            specular: Specular::Phong,
+        }
+    }
+}
+
+impl Phong {
+    // to_light and view are unit vectors away from the surface, normal faces both of them
+    pub fn highlight(&self, normal: Point, to_light: Point, view: Point) -> Float {
+        let cos = match self.specular {
+            Specular::Phong => (to_light * -1.0).reflect(normal) * view,
+            Specular::BlinnPhong => normal * (to_light + view).normalize(),
+        };
+        cos.max(0.0).powf(self.shininess)
+    }
+}
+
+// how a ray splits up at the surface of a transparent material
+pub struct Interface {
+    pub reflected: Point,
+    // None on total internal reflection
+    pub refracted: Option<Point>,
+    // fraction of the light that is reflected, the rest is refracted
+    pub reflectance: Float,
+}
+
+impl Material {
+    // normals point inside of the entities, so a ray going along the normal enters the material
+    pub fn is_entered_by(direction: Point, normal: Point) -> bool {
+        direction * normal > 0.0
+    }
+}
+
+// how a ray splits up at a smooth surface, outside and inside are the indices of refraction on
+// both sides of it
+pub fn interface(direction: Point, normal: Point, outside: Float, inside: Float) -> Interface {
+    let direction = direction.normalize();
+    let entering = Material::is_entered_by(direction, normal);
+    let (eta_i, eta_t) = if entering {
+        (outside, inside)
+    } else {
+        (inside, outside)
+    };
+    let facing = sampling::facing_normal(direction, normal.normalize());
+    let refracted = direction.refract(facing, eta_i / eta_t);
+    let reflectance = match refracted {
+        None => 1.0,
+        Some(_) => fresnel_dielectric(-(direction * facing), eta_i, eta_t),
+    };
+    Interface {
+        reflected: direction.reflect(facing),
+        refracted,
+        reflectance,
+    }
+}
+
+// exact fresnel reflectance of unpolarized light going from index eta_i to eta_t
+pub fn fresnel_dielectric(cos_i: Float, eta_i: Float, eta_t: Float) -> Float {
+    let cos_i = cos_i.clamp(0.0, 1.0);
+    let sin_t2 = (eta_i / eta_t).powi(2) * (1.0 - cos_i * cos_i);
+    if sin_t2 >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin_t2).sqrt();
+    let s = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    let p = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    (s * s + p * p) / 2.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsdf::Hit;
+
+    // glass below the surface, air above
+    fn glass(direction: Point, normal: Point) -> Interface {
+        interface(direction, normal, 1.0, 1.5)
+    }
+
+    #[test]
+    fn fresnel_limits() {
+        assert!((fresnel_dielectric(1.0, 1.0, 1.5) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(1.0, 1.5, 1.0) - 0.04).abs() < 1e-12);
+        assert!((fresnel_dielectric(0.0, 1.0, 1.5) - 1.0).abs() < 1e-12);
+        // brewster's angle has no p polarized reflection
+        let brewster = (1.5 as Float).atan();
+        let s = (brewster.cos() - 1.5 * (1.0 - (brewster.sin() / 1.5).powi(2)).sqrt())
+            / (brewster.cos() + 1.5 * (1.0 - (brewster.sin() / 1.5).powi(2)).sqrt());
+        assert!((fresnel_dielectric(brewster.cos(), 1.0, 1.5) - s * s / 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn rays_entering_and_leaving_follow_snell() {
+        // the normal of the surface z = 0 of a solid below it points down, into the solid
+        let normal = Point::new(0.0, 0.0, -1.0);
+        let angle: Float = 0.5;
+        let direction = Point::new(angle.sin(), 0.0, -angle.cos());
+
+        assert!(Material::is_entered_by(direction, normal));
+        let entering = glass(direction, normal);
+        let refracted = entering.refracted.unwrap();
+        assert!(refracted.z < 0.0);
+        assert!((refracted.x - angle.sin() / 1.5).abs() < 1e-12);
+        assert!((entering.reflected.z - angle.cos()).abs() < 1e-12);
+
+        assert!(!Material::is_entered_by(refracted, normal * -1.0));
+        let leaving = glass(refracted, normal * -1.0);
+        let out = leaving.refracted.unwrap();
+        assert!((out.x - direction.x).abs() < 1e-12 && (out.z - direction.z).abs() < 1e-12);
+        assert!((leaving.reflectance - entering.reflectance).abs() < 1e-12);
+    }
+
+    #[test]
+    fn total_internal_reflection() {
+        // glass below z = 0, left upwards at 60 degrees, above the critical angle of about 41.8
+        let normal = Point::new(0.0, 0.0, -1.0);
+        let angle = (60.0 as Float).to_radians();
+        let direction = Point::new(angle.sin(), 0.0, angle.cos());
+        assert!(!Material::is_entered_by(direction, normal));
+        let interface = glass(direction, normal);
+        assert!(interface.refracted.is_none());
+        assert_eq!(interface.reflectance, 1.0);
+        assert!(interface.reflected.z < 0.0);
+    }
+
+    #[test]
+    fn absorbing_glass_does_not_tint_at_its_boundaries() {
+        let cyan = Color::new(0.4, 1.0, 1.0);
+        let refracted = |material: Material| {
+            let hit = Hit::new(
+                Point::new(0.0, 0.0, 1.0),
+                Point::new(0.0, 0.0, 1.0),
+                1.0,
+                1.5,
+            );
+            let sample = material
+                .bsdf
+                .specular(&hit)
+                .into_iter()
+                .find(|s| s.refracted);
+            sample.unwrap().weight.channels()
+        };
+        let clear = Medium::new(1.5);
+        let [r, g, b] = refracted(Material::new_transparent(cyan, 1.0, clear));
+        assert!(r < g && g == b);
+        let absorbing = Medium {
+            absorption: Color::new(0.5, 0.0, 0.0),
+            ..clear
+        };
+        for material in [
+            Material::new_transparent(cyan, 1.0, absorbing),
+            Material::new_rough_transparent(cyan, 0.0, absorbing),
+        ] {
+            let [r, g, b] = refracted(material);
+            // only the fresnel transmittance is left
+            assert!(r == g && g == b && r > 0.9, "{:?}", [r, g, b]);
+        }
+    }
+
+    #[test]
+    fn highlights_peak_in_the_mirror_direction() {
+        let normal = Point::new(0.0, 0.0, 1.0);
+        let to_light = Point::new(0.6, 0.0, 0.8);
+        let mirror = Point::new(-0.6, 0.0, 0.8);
+        let off = Point::new(-0.8, 0.0, 0.6);
+        for &specular in [Specular::Phong, Specular::BlinnPhong].iter() {
+            let phong = Phong {
+                specular,
+                ..Phong::default()
+            };
+            assert!((phong.highlight(normal, to_light, mirror) - 1.0).abs() < 1e-12);
+            assert!(phong.highlight(normal, to_light, off) < 1.0);
+        }
+        // the halfway vector moves half as far as the view, blinn-phong highlights are wider
+        let phong = Phong::default().highlight(normal, to_light, off);
+        let blinn = Phong {
+            specular: Specular::BlinnPhong,
+            ..Phong::default()
         }
+        .highlight(normal, to_light, off);
+        assert!(blinn > phong);
     }
 }
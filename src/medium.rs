@@ -0,0 +1,226 @@
+use crate::{drawing::Color, Float};
+
+// what a ray travels through inside of a transparent material
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Medium {
+    pub refraction_coefficient: Float,
+    // overlapping transparent materials hide the boundaries of those with lower priority
+    pub priority: u32,
+    // beer-lambert absorption per unit of distance travelled inside, for every channel
+    pub absorption: Color,
+}
+
+impl Medium {
+    pub fn new(refraction_coefficient: Float) -> Self {
+        Self {
+            refraction_coefficient,
+            priority: 0,
+            absorption: Color::BLACK,
+        }
+    }
+
+    pub fn absorbs(&self) -> bool {
+        self.absorption != Color::BLACK
+    }
+}
+
+// transparent materials a path is currently inside of, outermost first
+//
+// overlapping transparent entities are resolved by priority: inside of a material only the
+// boundaries of materials with at least its priority are real, the others are skipped, so water
+// inside of a glass does not have to be modelled without the part that overlaps the glass
+#[derive(Clone, Default)]
+pub struct MediumStack {
+    // media are told apart by the scene object they fill, not by their material, all faces of a
+    // cube or a mesh are one object
+    media: Vec<(usize, Medium)>,
+}
+
+impl MediumStack {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the medium with the highest priority, the most recently entered one wins ties
+    fn top<'a, I: Iterator<Item = &'a Medium>>(media: I) -> Option<&'a Medium> {
+        media.max_by_key(|medium| medium.priority)
+    }
+
+    fn index_of(&self, object: usize) -> Option<usize> {
+        self.media.iter().rposition(|&(inside, _)| inside == object)
+    }
+
+    // index of refraction on the other side of a boundary of the material of object, None if
+    // the boundary is hidden by a medium with a higher priority and the ray has to pass it
+    // unchanged
+    pub fn outside_of(&self, object: usize, material: &Medium, entering: bool) -> Option<Float> {
+        // on the way out the medium of the object itself is not on the other side
+        let left = if entering {
+            None
+        } else {
+            self.index_of(object)
+        };
+        let rest = self
+            .media
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| Some(i) != left)
+            .map(|(_, (_, medium))| medium);
+        match MediumStack::top(rest) {
+            None => Some(1.0),
+            Some(top) if top.priority > material.priority => None,
+            Some(top) => Some(top.refraction_coefficient),
+        }
+    }
+
+    // fraction of the light that is not absorbed on distance through the current medium
+    pub fn transmittance(&self, distance: Float) -> Color {
+        match MediumStack::top(self.media.iter().map(|(_, medium)| medium)) {
+            None => Color::WHITE,
+            Some(medium) => {
+                let [r, g, b] = medium.absorption.channels();
+                Color::new(
+                    (-r * distance).exp(),
+                    (-g * distance).exp(),
+                    (-b * distance).exp(),
+                )
+            }
+        }
+    }
+
+    // the path went through a boundary of object, which is filled with material
+    pub fn cross(&mut self, object: usize, material: &Medium, entering: bool) {
+        if entering {
+            self.media.push((object, *material));
+        } else if let Some(index) = self.index_of(object) {
+            self.media.remove(index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{drawing::Color, geometry::Point, material::Material, scene};
+    use std::path::Path;
+
+    fn medium(refraction_coefficient: Float, priority: u32) -> Medium {
+        Medium {
+            priority,
+            ..Medium::new(refraction_coefficient)
+        }
+    }
+
+    // scene objects
+    const WATER: usize = 3;
+    const GLASS: usize = 7;
+
+    #[test]
+    fn glass_in_water() {
+        let water = medium(1.33, 0);
+        let glass = medium(1.5, 1);
+        let mut stack = MediumStack::new();
+
+        // air into water into glass
+        assert_eq!(stack.outside_of(WATER, &water, true), Some(1.0));
+        stack.cross(WATER, &water, true);
+        assert_eq!(stack.outside_of(GLASS, &glass, true), Some(1.33));
+        stack.cross(GLASS, &glass, true);
+
+        // the water surface inside of the glass is not there
+        assert_eq!(stack.outside_of(WATER, &water, false), None);
+        stack.cross(WATER, &water, false);
+        assert_eq!(stack.outside_of(WATER, &water, true), None);
+        stack.cross(WATER, &water, true);
+
+        // glass back into water, then into air
+        assert_eq!(stack.outside_of(GLASS, &glass, false), Some(1.33));
+        stack.cross(GLASS, &glass, false);
+        assert_eq!(stack.outside_of(WATER, &water, false), Some(1.0));
+        stack.cross(WATER, &water, false);
+        assert!(stack.media.is_empty());
+    }
+
+    #[test]
+    fn bubble_in_water() {
+        let water = medium(1.33, 1);
+        let air = medium(1.0, 2);
+        let mut stack = MediumStack::new();
+        stack.cross(WATER, &water, true);
+        assert_eq!(stack.outside_of(GLASS, &air, true), Some(1.33));
+        stack.cross(GLASS, &air, true);
+        assert_eq!(stack.outside_of(GLASS, &air, false), Some(1.33));
+        stack.cross(GLASS, &air, false);
+        assert_eq!(stack.outside_of(WATER, &water, false), Some(1.0));
+    }
+
+    #[test]
+    fn overlapping_objects_of_one_material() {
+        // two intersecting glass spheres, the first one is left while inside of the second
+        let glass = medium(1.5, 0);
+        let mut stack = MediumStack::new();
+        stack.cross(1, &glass, true);
+        stack.cross(2, &glass, true);
+        stack.cross(1, &glass, false);
+        assert_eq!(stack.media.len(), 1);
+        assert_eq!(stack.media[0].0, 2);
+        // leaving an object the path never entered changes nothing
+        stack.cross(5, &glass, false);
+        assert_eq!(stack.media.len(), 1);
+    }
+
+    #[test]
+    fn absorption_of_the_current_medium() {
+        let mut water = medium(1.33, 0);
+        water.absorption = Color::new(0.0, 0.5, 2.0);
+        let mut stack = MediumStack::new();
+        assert_eq!(stack.transmittance(10.0).channels(), [1.0, 1.0, 1.0]);
+        stack.cross(WATER, &water, true);
+        let [r, g, b] = stack.transmittance(2.0).channels();
+        assert_eq!(r, 1.0);
+        assert!((g - (-1.0 as Float).exp()).abs() < 1e-12);
+        assert!((b - (-4.0 as Float).exp()).abs() < 1e-12);
+        // twice the distance is the square of the transmittance
+        let [_, g4, _] = stack.transmittance(4.0).channels();
+        assert!((g4 - g * g).abs() < 1e-12);
+    }
+
+    #[test]
+    fn leaving_an_unknown_medium() {
+        let stack = MediumStack::new();
+        assert_eq!(stack.outside_of(GLASS, &medium(1.5, 0), false), Some(1.0));
+    }
+
+    #[test]
+    fn rays_leave_cubes_through_other_faces() {
+        // the twelve triangles of a cube are one object, so the glass is left through any of them
+        let scene = scene::parse(
+            "[[entities]]\ntype = \"absolute_cube\"\nfrom = [-1.0, -1.0, -1.0]\n\
+             to = [1.0, 1.0, 1.0]\nmaterial = { type = \"transparent\", \
+             color = [1.0, 1.0, 1.0], transparency = 1.0, refraction_coefficient = 1.5 }\n",
+            Path::new(""),
+        )
+        .unwrap();
+        let world = scene.world;
+        let direction = Point::new(0.0, 0.0, 1.0);
+        let mut media = MediumStack::new();
+
+        let enter = world
+            .cast_ray(Point::new(0.3, 0.2, -5.0), direction)
+            .unwrap();
+        let medium = enter.material.medium.unwrap();
+        let entering = Material::is_entered_by(direction, enter.intersection.normal);
+        assert!(entering);
+        assert_eq!(media.outside_of(enter.object, &medium, entering), Some(1.0));
+        media.cross(enter.object, &medium, entering);
+
+        let start = enter.intersection.intersection_point + direction * 0.001;
+        let exit = world.cast_ray(start, direction).unwrap();
+        assert_ne!(exit.entity, enter.entity);
+        let entering = Material::is_entered_by(direction, exit.intersection.normal);
+        assert!(!entering);
+        assert_eq!(media.outside_of(exit.object, &medium, entering), Some(1.0));
+        media.cross(exit.object, &medium, entering);
+        assert!(media.media.is_empty());
+    }
+}
@@ -0,0 +1,576 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::{drawing::Color, geometry::Point, material::fresnel_dielectric, Float};
+
+// alpha below this makes the distribution a spike that floating point can not evaluate
+const MIN_ALPHA: Float = 0.0001;
+
+// reflectance of a metal, or of a dielectric coat that the ray does not go through
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fresnel {
+    // complex index of refraction eta + i k for every channel, seen from air
+    Conductor { eta: Color, k: Color },
+    // schlick's approximation from the color at normal incidence
+    Schlick(Color),
+    // index of refraction seen from air
+    Dielectric(Float),
+}
+
+impl Fresnel {
+    // measured indices of refraction at about 650, 550 and 450 nm
+    pub fn gold() -> Self {
+        Fresnel::Conductor {
+            eta: Color::new(0.143, 0.374, 1.442),
+            k: Color::new(3.983, 2.385, 1.603),
+        }
+    }
+
+    pub fn copper() -> Self {
+        Fresnel::Conductor {
+            eta: Color::new(0.200, 0.924, 1.102),
+            k: Color::new(3.912, 2.452, 2.142),
+        }
+    }
+
+    pub fn aluminium() -> Self {
+        Fresnel::Conductor {
+            eta: Color::new(1.657, 0.880, 0.521),
+            k: Color::new(9.224, 6.270, 4.837),
+        }
+    }
+
+    pub fn reflectance(&self, cos_i: Float) -> Color {
+        let cos_i = cos_i.clamp(0.0, 1.0);
+        match *self {
+            Fresnel::Conductor { eta, k } => {
+                let ([er, eg, eb], [kr, kg, kb]) = (eta.channels(), k.channels());
+                Color::new(
+                    fresnel_conductor(cos_i, er, kr),
+                    fresnel_conductor(cos_i, eg, kg),
+                    fresnel_conductor(cos_i, eb, kb),
+                )
+            }
+            Fresnel::Schlick(f0) => {
+                let weight = (1.0 - cos_i).powi(5);
+                f0 * (1.0 - weight) + Color::WHITE * weight
+            }
+            Fresnel::Dielectric(eta) => Color::WHITE * fresnel_dielectric(cos_i, 1.0, eta),
+        }
+    }
+}
+
+// exact fresnel reflectance of unpolarized light going from air into a conductor
+pub fn fresnel_conductor(cos_i: Float, eta: Float, k: Float) -> Float {
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let p = s * (t3 - t4) / (t3 + t4);
+    (s + p) / 2.0
+}
+
+// rough surfaces made of tiny perfect mirrors, or tiny perfect glass surfaces, with normals
+// distributed by ggx
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Microfacet {
+    Conductor { roughness: Float, fresnel: Fresnel },
+    // the indices of refraction come from the material and the medium around it
+    Dielectric { roughness: Float },
+}
+
+pub struct MicrofacetSample {
+    pub direction: Point,
+    // bsdf * cos / pdf
+    pub weight: Color,
+    // solid angle density of direction
+    pub pdf: Float,
+    pub refracted: bool,
+}
+
+impl Microfacet {
+    pub fn with_roughness(self, roughness: Float) -> Self {
+        match self {
+            Microfacet::Conductor { fresnel, .. } => Microfacet::Conductor { roughness, fresnel },
+            Microfacet::Dielectric { .. } => Microfacet::Dielectric { roughness },
+        }
+    }
+}
+
+// all of these take the incoming ray direction and the normal of the entity, which points
+// inside, outside and inside are the indices of refraction on both sides of the surface
+impl Microfacet {
+    pub fn sample<R: Rng + ?Sized>(
+        &self,
+        direction: Point,
+        normal: Point,
+        outside: Float,
+        inside: Float,
+        rng: &mut R,
+    ) -> Option<MicrofacetSample> {
+        let frame = Frame::new(normal);
+        let wo = frame.to_local(direction.normalize() * -1.0);
+        let sample = match *self {
+            Microfacet::Conductor { roughness, fresnel } => {
+                let (wo, flip) = two_sided(wo);
+                let ggx = Ggx::new(roughness);
+                let wm = ggx.sample_visible(wo, rng);
+                let wi = reflect(wo, wm);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                LocalSample {
+                    wi: flip(wi),
+                    weight: fresnel.reflectance(wo * wm) * (ggx.g(wo, wi) / ggx.g1(wo)),
+                    pdf: ggx.visible_d(wo, wm) / (4.0 * (wo * wm).abs()),
+                    refracted: false,
+                }
+            }
+            Microfacet::Dielectric { roughness } => {
+                let ggx = Ggx::new(roughness);
+                let wm = ggx.sample_visible(wo, rng);
+                let cos_om = wo * wm;
+                if cos_om * wo.z <= 0.0 {
+                    return None;
+                }
+                let (eta_i, eta_t) = sides(wo, outside, inside);
+                let reflectance = fresnel_dielectric(cos_om.abs(), eta_i, eta_t);
+                if rng.gen::<Float>() < reflectance {
+                    let wi = reflect(wo, wm);
+                    if wi.z * wo.z <= 0.0 {
+                        return None;
+                    }
+                    LocalSample {
+                        wi,
+                        weight: Color::WHITE * (ggx.g(wo, wi) / ggx.g1(wo)),
+                        pdf: ggx.visible_d(wo, wm) / (4.0 * cos_om.abs()) * reflectance,
+                        refracted: false,
+                    }
+                } else {
+                    let facing = if cos_om > 0.0 { wm } else { wm * -1.0 };
+                    let wi = (wo * -1.0).refract(facing, eta_i / eta_t)?;
+                    if wi.z * wo.z >= 0.0 {
+                        return None;
+                    }
+                    let pdf = dielectric_pdf(&ggx, wo, wi, outside, inside);
+                    let value = dielectric_eval(&ggx, wo, wi, outside, inside);
+                    if pdf <= 0.0 {
+                        return None;
+                    }
+                    LocalSample {
+                        wi,
+                        weight: Color::WHITE * (value / pdf),
+                        pdf,
+                        refracted: true,
+                    }
+                }
+            }
+        };
+        Some(MicrofacetSample {
+            direction: frame.to_world(sample.wi),
+            weight: sample.weight,
+            pdf: sample.pdf,
+            refracted: sample.refracted,
+        })
+    }
+
+    // bsdf times the cosine towards to_light
+    pub fn eval(
+        &self,
+        direction: Point,
+        normal: Point,
+        to_light: Point,
+        outside: Float,
+        inside: Float,
+    ) -> Color {
+        let frame = Frame::new(normal);
+        let wo = frame.to_local(direction.normalize() * -1.0);
+        let wi = frame.to_local(to_light.normalize());
+        match *self {
+            Microfacet::Conductor { roughness, fresnel } => {
+                let (wo, flip) = two_sided(wo);
+                let wi = flip(wi);
+                if wi.z <= 0.0 || wo.z <= 0.0 {
+                    return Color::BLACK;
+                }
+                let wm = match half_vector(wo, wi) {
+                    None => return Color::BLACK,
+                    Some(wm) => wm,
+                };
+                let ggx = Ggx::new(roughness);
+                fresnel.reflectance(wo * wm) * (ggx.d(wm) * ggx.g(wo, wi) / (4.0 * wo.z))
+            }
+            Microfacet::Dielectric { roughness } => {
+                Color::WHITE * dielectric_eval(&Ggx::new(roughness), wo, wi, outside, inside)
+            }
+        }
+    }
+
+    // solid angle density of sample returning to_light
+    pub fn pdf(
+        &self,
+        direction: Point,
+        normal: Point,
+        to_light: Point,
+        outside: Float,
+        inside: Float,
+    ) -> Float {
+        let frame = Frame::new(normal);
+        let wo = frame.to_local(direction.normalize() * -1.0);
+        let wi = frame.to_local(to_light.normalize());
+        match *self {
+            Microfacet::Conductor { roughness, .. } => {
+                let (wo, flip) = two_sided(wo);
+                let wi = flip(wi);
+                if wi.z <= 0.0 || wo.z <= 0.0 {
+                    return 0.0;
+                }
+                match half_vector(wo, wi) {
+                    None => 0.0,
+                    Some(wm) => Ggx::new(roughness).visible_d(wo, wm) / (4.0 * (wo * wm).abs()),
+                }
+            }
+            Microfacet::Dielectric { roughness } => {
+                dielectric_pdf(&Ggx::new(roughness), wo, wi, outside, inside)
+            }
+        }
+    }
+}
+
+struct LocalSample {
+    wi: Point,
+    weight: Color,
+    pdf: Float,
+    refracted: bool,
+}
+
+// trowbridge-reitz distribution of microfacet normals in the local frame, z is the normal of
+// the surface, with height correlated smith shadowing
+struct Ggx {
+    alpha: Float,
+}
+
+impl Ggx {
+    // the roughness is squared like in most renderers, so that it looks about linear
+    fn new(roughness: Float) -> Self {
+        Self {
+            alpha: (roughness * roughness).max(MIN_ALPHA),
+        }
+    }
+
+    fn d(&self, m: Point) -> Float {
+        let cos2 = m.z * m.z;
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        let alpha2 = self.alpha * self.alpha;
+        let e = 1.0 + tan2 / alpha2;
+        1.0 / (PI * alpha2 * cos2 * cos2 * e * e)
+    }
+
+    fn lambda(&self, w: Point) -> Float {
+        let cos2 = w.z * w.z;
+        if cos2 <= 0.0 {
+            return Float::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    fn g1(&self, w: Point) -> Float {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    fn g(&self, wo: Point, wi: Point) -> Float {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // density of the normals visible from w
+    fn visible_d(&self, w: Point, m: Point) -> Float {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z.abs() * self.d(m) * (w * m).abs()
+    }
+
+    // samples visible_d, heitz 2018, the normal is always above the surface
+    fn sample_visible<R: Rng + ?Sized>(&self, w: Point, rng: &mut R) -> Point {
+        let mut stretched = Point::new(self.alpha * w.x, self.alpha * w.y, w.z).normalize();
+        if stretched.z < 0.0 {
+            stretched = stretched * -1.0;
+        }
+        let t1 = if stretched.z < 0.99999 {
+            Point::new(0.0, 0.0, 1.0).dot(stretched).normalize()
+        } else {
+            Point::new(1.0, 0.0, 0.0)
+        };
+        let t2 = stretched.dot(t1);
+        let r = rng.gen::<Float>().sqrt();
+        let phi = 2.0 * PI * rng.gen::<Float>();
+        let (x, y) = (r * phi.cos(), r * phi.sin());
+        let h = (1.0 - x * x).sqrt();
+        let s = (1.0 + stretched.z) / 2.0;
+        let y = (1.0 - s) * h + s * y;
+        let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+        let m = t1 * x + t2 * y + stretched * z;
+        Point::new(self.alpha * m.x, self.alpha * m.y, m.z.max(0.000001)).normalize()
+    }
+}
+
+fn dielectric_eval(ggx: &Ggx, wo: Point, wi: Point, outside: Float, inside: Float) -> Float {
+    let (wm, etap) = match generalized_half_vector(wo, wi, outside, inside) {
+        None => return 0.0,
+        Some(half) => half,
+    };
+    let (cos_o, cos_i) = (wo.z, wi.z);
+    let (eta_i, eta_t) = sides(wo, outside, inside);
+    let reflectance = fresnel_dielectric((wo * wm).abs(), eta_i, eta_t);
+    if cos_o * cos_i > 0.0 {
+        ggx.d(wm) * ggx.g(wo, wi) * reflectance / (4.0 * cos_o.abs())
+    } else {
+        // like the smooth refraction the radiance is not scaled by the squared ratio of the
+        // indices, it cancels out once the path leaves the medium again
+        let denominator = (wi * wm + (wo * wm) / etap).powi(2);
+        ggx.d(wm) * ggx.g(wo, wi) * (1.0 - reflectance) * ((wi * wm) * (wo * wm)).abs()
+            / (cos_o.abs() * denominator)
+    }
+}
+
+fn dielectric_pdf(ggx: &Ggx, wo: Point, wi: Point, outside: Float, inside: Float) -> Float {
+    let (wm, etap) = match generalized_half_vector(wo, wi, outside, inside) {
+        None => return 0.0,
+        Some(half) => half,
+    };
+    let (eta_i, eta_t) = sides(wo, outside, inside);
+    let reflectance = fresnel_dielectric((wo * wm).abs(), eta_i, eta_t);
+    if wo.z * wi.z > 0.0 {
+        ggx.visible_d(wo, wm) / (4.0 * (wo * wm).abs()) * reflectance
+    } else {
+        let denominator = (wi * wm + (wo * wm) / etap).powi(2);
+        ggx.visible_d(wo, wm) * (wi * wm).abs() / denominator * (1.0 - reflectance)
+    }
+}
+
+// microfacet normal that turns wo into wi, above the surface, together with the ratio of the
+// indices of refraction of the side of wi and the side of wo, None for microfacets that face away
+fn generalized_half_vector(
+    wo: Point,
+    wi: Point,
+    outside: Float,
+    inside: Float,
+) -> Option<(Point, Float)> {
+    let (cos_o, cos_i) = (wo.z, wi.z);
+    if cos_o == 0.0 || cos_i == 0.0 {
+        return None;
+    }
+    let etap = if cos_o * cos_i > 0.0 {
+        1.0
+    } else if cos_o > 0.0 {
+        inside / outside
+    } else {
+        outside / inside
+    };
+    let wm = half_vector(wo, wi * etap)?;
+    if (wm * wi) * cos_i < 0.0 || (wm * wo) * cos_o < 0.0 {
+        return None;
+    }
+    Some((wm, etap))
+}
+
+// normalized sum, turned above the surface
+fn half_vector(a: Point, b: Point) -> Option<Point> {
+    let sum = a + b;
+    let len = sum.len();
+    if len < 0.000000001 {
+        return None;
+    }
+    let half = sum / len;
+    Some(if half.z < 0.0 { half * -1.0 } else { half })
+}
+
+fn reflect(wo: Point, wm: Point) -> Point {
+    wm * (2.0 * (wo * wm)) - wo
+}
+
+// indices of refraction on the side of wo and on the other side
+fn sides(wo: Point, outside: Float, inside: Float) -> (Float, Float) {
+    if wo.z > 0.0 {
+        (outside, inside)
+    } else {
+        (inside, outside)
+    }
+}
+
+// metals look the same from both sides, wo is moved above the surface and the returned function
+// moves other directions along
+fn two_sided(wo: Point) -> (Point, fn(Point) -> Point) {
+    if wo.z < 0.0 {
+        (Point::new(wo.x, wo.y, -wo.z), |w| {
+            Point::new(w.x, w.y, -w.z)
+        })
+    } else {
+        (wo, |w| w)
+    }
+}
+
+// local coordinates with z pointing outside of the entity
+struct Frame {
+    tangent: Point,
+    bitangent: Point,
+    normal: Point,
+}
+
+impl Frame {
+    fn new(normal: Point) -> Self {
+        let normal = normal.normalize() * -1.0;
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        Self {
+            tangent,
+            bitangent,
+            normal,
+        }
+    }
+
+    fn to_local(&self, v: Point) -> Point {
+        Point::new(v * self.tangent, v * self.bitangent, v * self.normal)
+    }
+
+    fn to_world(&self, v: Point) -> Point {
+        self.tangent * v.x + self.bitangent * v.y + self.normal * v.z
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    fn uniform_sphere(rng: &mut StdRng) -> Point {
+        let z = 1.0 - 2.0 * rng.gen::<Float>();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.gen::<Float>();
+        Point::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    // the entity lies below z = 0, so its normal points down
+    const NORMAL: Point = Point {
+        x: 0.0,
+        y: 0.0,
+        z: -1.0,
+    };
+
+    fn incoming(angle: Float) -> Point {
+        Point::new(angle.sin(), 0.0, -angle.cos())
+    }
+
+    #[test]
+    fn conductor_reflectance_at_normal_incidence() {
+        let (eta, k) = (0.2, 3.9);
+        let expected = ((eta - 1.0) * (eta - 1.0) + k * k) / ((eta + 1.0) * (eta + 1.0) + k * k);
+        assert!((fresnel_conductor(1.0, eta, k) - expected).abs() < 1e-12);
+        assert!((fresnel_conductor(0.0, eta, k) - 1.0).abs() < 1e-9);
+        // gold reflects more red than blue
+        let [r, _, b] = Fresnel::gold().reflectance(1.0).channels();
+        assert!(r > 0.9 && b < 0.5);
+    }
+
+    // samples have to agree with eval and pdf, and the pdf has to integrate to the chance that
+    // sample returns a direction at all
+    fn check_sampling(microfacet: Microfacet, angle: Float, outside: Float, inside: Float) {
+        let mut rng = StdRng::seed_from_u64(11);
+        let direction = incoming(angle);
+        let samples = 200_000;
+        let mut sampled = 0.0;
+        let mut mean_weight = 0.0;
+        for _ in 0..samples {
+            if let Some(sample) = microfacet.sample(direction, NORMAL, outside, inside, &mut rng) {
+                sampled += 1.0;
+                mean_weight += sample.weight.channels()[0];
+                let pdf = microfacet.pdf(direction, NORMAL, sample.direction, outside, inside);
+                let value = microfacet.eval(direction, NORMAL, sample.direction, outside, inside);
+                assert!(
+                    (pdf - sample.pdf).abs() <= 1e-6 * pdf.max(1.0),
+                    "{:?}",
+                    microfacet
+                );
+                let weight = value.channels()[0] / pdf;
+                assert!((weight - sample.weight.channels()[0]).abs() < 1e-6 * weight.max(1.0));
+            }
+        }
+        let (sampled, mean_weight) = (sampled / samples as Float, mean_weight / samples as Float);
+
+        let mut integral_pdf = 0.0;
+        let mut integral_eval = 0.0;
+        for _ in 0..samples {
+            let to_light = uniform_sphere(&mut rng);
+            integral_pdf += microfacet.pdf(direction, NORMAL, to_light, outside, inside);
+            integral_eval += microfacet
+                .eval(direction, NORMAL, to_light, outside, inside)
+                .channels()[0];
+        }
+        let integral_pdf = integral_pdf * 4.0 * PI / samples as Float;
+        let integral_eval = integral_eval * 4.0 * PI / samples as Float;
+        assert!(
+            (integral_pdf - sampled).abs() < 0.03,
+            "{} {}",
+            integral_pdf,
+            sampled
+        );
+        assert!(
+            (integral_eval - mean_weight).abs() < 0.03,
+            "{} {}",
+            integral_eval,
+            mean_weight
+        );
+        assert!(mean_weight <= 1.0 + 1e-9);
+    }
+
+    #[test]
+    fn rough_conductor_sampling_matches_eval() {
+        for &angle in [0.1, 0.8, 1.3].iter() {
+            let microfacet = Microfacet::Conductor {
+                roughness: 0.6,
+                fresnel: Fresnel::copper(),
+            };
+            check_sampling(microfacet, angle, 1.0, 1.0);
+        }
+    }
+
+    #[test]
+    fn rough_dielectric_sampling_matches_eval() {
+        let microfacet = Microfacet::Dielectric { roughness: 0.5 };
+        for &angle in [0.2, 1.0].iter() {
+            check_sampling(microfacet, angle, 1.0, 1.5);
+            // from inside of the glass
+            check_sampling(microfacet, angle + PI, 1.0, 1.5);
+        }
+    }
+
+    #[test]
+    fn white_rough_conductor_loses_little_energy() {
+        // the single scattering model only misses the light that bounces between microfacets
+        let microfacet = Microfacet::Conductor {
+            roughness: 0.3,
+            fresnel: Fresnel::Schlick(Color::WHITE),
+        };
+        let mut rng = StdRng::seed_from_u64(5);
+        let samples = 50_000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            if let Some(sample) = microfacet.sample(incoming(0.3), NORMAL, 1.0, 1.0, &mut rng) {
+                total += sample.weight.channels()[0];
+            }
+        }
+        assert!(total / samples as Float > 0.95);
+    }
+}
@@ -0,0 +1,169 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{geometry::Point, Float};
+
+const SIZE: usize = 256;
+
+// lattice noise: perlin gradient noise and worley cellular noise, the lattice is shuffled from
+// a seed, so the same seed always gives the same noise
+pub struct Noise {
+    permutation: Vec<usize>,
+    // unit gradients of perlin noise at the lattice points
+    gradients: Vec<Point>,
+    // feature points of worley noise inside the cells
+    features: Vec<Point>,
+}
+
+impl Noise {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut permutation: Vec<usize> = (0..SIZE).collect();
+        for i in (1..SIZE).rev() {
+            permutation.swap(i, rng.gen_range(0, i + 1));
+        }
+        let gradients = (0..SIZE)
+            .map(|_| loop {
+                let gradient = Point::new(
+                    rng.gen_range(-1.0, 1.0),
+                    rng.gen_range(-1.0, 1.0),
+                    rng.gen_range(-1.0, 1.0),
+                );
+                let len = gradient.len();
+                if len > 1e-3 && len <= 1.0 {
+                    break gradient / len;
+                }
+            })
+            .collect();
+        let features = (0..SIZE)
+            .map(|_| Point::new(rng.gen(), rng.gen(), rng.gen()))
+            .collect();
+        Self {
+            permutation,
+            gradients,
+            features,
+        }
+    }
+
+    fn hash(&self, x: i64, y: i64, z: i64) -> usize {
+        let p = |i: i64, offset: usize| {
+            self.permutation[(i.rem_euclid(SIZE as i64) as usize + offset) % SIZE]
+        };
+        p(z, p(y, p(x, 0)))
+    }
+
+    // smooth noise about in [-1, 1], zero at the lattice points
+    pub fn perlin(&self, point: Point) -> Float {
+        let (x0, y0, z0) = (point.x.floor(), point.y.floor(), point.z.floor());
+        let (fx, fy, fz) = (point.x - x0, point.y - y0, point.z - z0);
+        let (x0, y0, z0) = (x0 as i64, y0 as i64, z0 as i64);
+        let corner = |dx: i64, dy: i64, dz: i64| {
+            let gradient = self.gradients[self.hash(x0 + dx, y0 + dy, z0 + dz)];
+            gradient * Point::new(fx - dx as Float, fy - dy as Float, fz - dz as Float)
+        };
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        let x00 = lerp(corner(0, 0, 0), corner(1, 0, 0), u);
+        let x10 = lerp(corner(0, 1, 0), corner(1, 1, 0), u);
+        let x01 = lerp(corner(0, 0, 1), corner(1, 0, 1), u);
+        let x11 = lerp(corner(0, 1, 1), corner(1, 1, 1), u);
+        lerp(lerp(x00, x10, v), lerp(x01, x11, v), w)
+    }
+
+    // fractional brownian motion: octaves of noise, each with twice the frequency and half the
+    // amplitude of the one before, about in [-1, 1]
+    pub fn fbm(&self, point: Point, octaves: usize) -> Float {
+        self.octaves(point, octaves, |noise| noise)
+    }
+
+    // like fbm of the absolute value of the noise, in [0, 1] with creases where it crosses zero
+    pub fn turbulence(&self, point: Point, octaves: usize) -> Float {
+        self.octaves(point, octaves, Float::abs)
+    }
+
+    fn octaves<F: Fn(Float) -> Float>(&self, point: Point, octaves: usize, f: F) -> Float {
+        let (mut sum, mut total) = (0.0, 0.0);
+        let mut amplitude = 1.0;
+        let mut point = point;
+        for _ in 0..octaves.max(1) {
+            sum += f(self.perlin(point)) * amplitude;
+            total += amplitude;
+            amplitude *= 0.5;
+            point = point * 2.0;
+        }
+        sum / total
+    }
+
+    // distance to the closest of the feature points, one of them is in every unit cell
+    pub fn worley(&self, point: Point) -> Float {
+        let (x0, y0, z0) = (
+            point.x.floor() as i64,
+            point.y.floor() as i64,
+            point.z.floor() as i64,
+        );
+        let mut closest = Float::INFINITY;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let (x, y, z) = (x0 + dx, y0 + dy, z0 + dz);
+                    let feature = Point::new(x as Float, y as Float, z as Float)
+                        + self.features[self.hash(x, y, z)];
+                    closest = closest.min((feature - point).len());
+                }
+            }
+        }
+        closest
+    }
+}
+
+// quintic smoothstep, its first and second derivatives are zero at the lattice points
+fn fade(t: Float) -> Float {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: Float, b: Float, t: Float) -> Float {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> impl Iterator<Item = Point> {
+        (0..200).map(|i| {
+            let i = i as Float;
+            Point::new(i * 0.37 - 20.0, i * 0.113 + 3.0, -i * 0.071)
+        })
+    }
+
+    #[test]
+    fn seeded_noise_is_deterministic() {
+        let (a, b, other) = (Noise::new(3), Noise::new(3), Noise::new(4));
+        let point = Point::new(1.3, -2.7, 0.4);
+        assert_eq!(a.perlin(point), b.perlin(point));
+        assert_eq!(a.worley(point), b.worley(point));
+        assert_ne!(a.perlin(point), other.perlin(point));
+    }
+
+    #[test]
+    fn perlin_vanishes_on_the_lattice() {
+        let noise = Noise::new(0);
+        assert_eq!(noise.perlin(Point::new(3.0, -7.0, 12.0)), 0.0);
+        for point in points() {
+            assert!(noise.perlin(point).abs() <= 1.0);
+            let turbulence = noise.turbulence(point, 5);
+            assert!((0.0..=1.0).contains(&turbulence));
+        }
+    }
+
+    #[test]
+    fn worley_is_the_distance_to_a_feature_point() {
+        let noise = Noise::new(1);
+        for point in points() {
+            let distance = noise.worley(point);
+            // the feature of the cell of the point is at most a diagonal away
+            assert!((0.0..=(3.0 as Float).sqrt()).contains(&distance));
+        }
+        let cell = Point::new(5.0, 6.0, 7.0);
+        let feature = cell + noise.features[noise.hash(5, 6, 7)];
+        assert!(noise.worley(feature) < 1e-12);
+    }
+}
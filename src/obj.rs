@@ -0,0 +1,654 @@
+use std::{collections::HashMap, fmt, fs, io, path::Path, str::SplitWhitespace};
+
+use crate::{
+    drawing::Color,
+    entities::{Entity, Mesh, MeshBuffers},
+    geometry::Point,
+    material::{Material, Phong, Specular},
+    medium::Medium,
+    Float,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(String, io::Error),
+    Parse {
+        file: String,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjError::Io(file, error) => write!(f, "could not read '{}': {}", file, error),
+            ObjError::Parse {
+                file,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", file, line, message),
+        }
+    }
+}
+
+pub struct ObjOptions {
+    // used for faces without usemtl
+    pub default_material: Material,
+    // applied to every position, scaling first
+    pub scale: Float,
+    pub translation: Point,
+}
+
+impl ObjOptions {
+    pub fn new(default_material: Material) -> Self {
+        Self {
+            default_material,
+            scale: 1.0,
+            translation: Point::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// loads a wavefront obj file together with its mtl libraries, one mesh per group
+pub fn load<P: AsRef<Path>>(
+    path: P,
+    options: &ObjOptions,
+) -> Result<Vec<(Entity, Material)>, ObjError> {
+    let path = path.as_ref();
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let text = read(path)?;
+    parse(&text, &path.display().to_string(), options, |library| {
+        let library_path = directory.join(library);
+        Ok((library_path.display().to_string(), read(&library_path)?))
+    })
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io(path.display().to_string(), error))
+}
+
+// a vertex of a face: indices of position, texture coordinate and normal
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+struct Group {
+    faces: Vec<([FaceVertex; 3], usize)>,
+    // line of every face, errors about the whole mesh point at its first face
+    lines: Vec<usize>,
+}
+
+impl Group {
+    fn new() -> Self {
+        Self {
+            faces: Vec::new(),
+            lines: Vec::new(),
+        }
+    }
+}
+
+// load_library returns the name and the contents of an mtl library referenced by mtllib
+pub fn parse<F>(
+    text: &str,
+    file: &str,
+    options: &ObjOptions,
+    mut load_library: F,
+) -> Result<Vec<(Entity, Material)>, ObjError>
+where
+    F: FnMut(&str) -> Result<(String, String), ObjError>,
+{
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut library: HashMap<String, Material> = HashMap::new();
+    // materials used so far, 0 is the default material
+    let mut materials = vec![options.default_material.clone()];
+    let mut material_indices: HashMap<String, usize> = HashMap::new();
+    let mut current_material = 0;
+    let mut groups = vec![Group::new()];
+
+    for (number, line) in text.lines().enumerate() {
+        let mut parser = LineParser::new(line, file, number + 1);
+        let keyword = match parser.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+        match keyword {
+            "v" => positions.push(parser.point()? * options.scale + options.translation),
+            "vn" => normals.push(parser.point()?),
+            "vt" => {
+                let u = parser.float()?;
+                let v = parser.optional_float()?.unwrap_or(0.0);
+                uvs.push((u, v));
+            }
+            "f" => {
+                let mut vertices = Vec::new();
+                while let Some(vertex) = parser.next() {
+                    vertices.push(parser.face_vertex(
+                        vertex,
+                        positions.len(),
+                        uvs.len(),
+                        normals.len(),
+                    )?);
+                }
+                if vertices.len() < 3 {
+                    return Err(parser.error("a face needs at least three vertices"));
+                }
+                // convex polygons are split into a fan around the first vertex, obj faces are
+                // counter clockwise around their outside, the winding is flipped so that the
+                // face normals point inside like those of all other entities
+                let group = groups.last_mut().expect("there is always a group");
+                for i in 1..vertices.len() - 1 {
+                    group.faces.push((
+                        [vertices[0], vertices[i + 1], vertices[i]],
+                        current_material,
+                    ));
+                    group.lines.push(number + 1);
+                }
+            }
+            // a new group only starts once the current one has faces
+            "g" | "o"
+                if !groups
+                    .last()
+                    .expect("there is always a group")
+                    .faces
+                    .is_empty() =>
+            {
+                groups.push(Group::new())
+            }
+            "usemtl" => {
+                let name = parser.rest();
+                let material = match library.get(name) {
+                    Some(material) => material.clone(),
+                    None => return Err(parser.error(&format!("unknown material '{}'", name))),
+                };
+                current_material = *material_indices.entry(name.to_string()).or_insert_with(|| {
+                    materials.push(material);
+                    materials.len() - 1
+                });
+            }
+            "mtllib" => {
+                for name in parser.rest().split_whitespace() {
+                    let (library_file, library_text) = load_library(name)?;
+                    library.extend(parse_mtl(&library_text, &library_file)?);
+                }
+            }
+            // smoothing groups, lines, points and so on are not supported and ignored
+            _ => {}
+        }
+    }
+
+    let mut entities = Vec::new();
+    for group in groups.iter().filter(|group| !group.faces.is_empty()) {
+        let mesh =
+            build_mesh(group, &positions, &uvs, &normals, &materials).map_err(|message| {
+                ObjError::Parse {
+                    file: file.to_string(),
+                    line: group.lines[0],
+                    message,
+                }
+            })?;
+        entities.push((Entity::Mesh(mesh), options.default_material.clone()));
+    }
+    Ok(entities)
+}
+
+// obj indexes positions, uvs and normals separately, meshes need one index per vertex
+fn build_mesh(
+    group: &Group,
+    positions: &[Point],
+    uvs: &[(Float, Float)],
+    normals: &[Point],
+    materials: &[Material],
+) -> Result<Mesh, String> {
+    let mut vertices: HashMap<FaceVertex, usize> = HashMap::new();
+    let mut buffers = MeshBuffers::new(Vec::new(), Vec::new());
+    let mut vertex_uvs = Vec::new();
+    let mut vertex_normals = Vec::new();
+    let has_uvs = group
+        .faces
+        .iter()
+        .any(|(face, _)| face.iter().any(|v| v.1.is_some()));
+    let has_normals = group
+        .faces
+        .iter()
+        .any(|(face, _)| face.iter().any(|v| v.2.is_some()));
+
+    for (face, material) in group.faces.iter() {
+        let mut indices = [0; 3];
+        for (i, vertex) in face.iter().enumerate() {
+            indices[i] = *vertices.entry(*vertex).or_insert_with(|| {
+                buffers.positions.push(positions[vertex.0]);
+                vertex_uvs.push(vertex.1.map(|uv| uvs[uv]).unwrap_or((0.0, 0.0)));
+                // a zero normal makes the mesh fall back to the flat face normal
+                vertex_normals.push(
+                    vertex
+                        .2
+                        .map(|normal| normals[normal])
+                        .unwrap_or_else(|| Point::new(0.0, 0.0, 0.0)),
+                );
+                buffers.positions.len() - 1
+            });
+        }
+        buffers.faces.push(indices);
+        buffers.face_materials.push(*material);
+    }
+
+    if has_uvs {
+        buffers.uvs = Some(vertex_uvs);
+    }
+    if has_normals {
+        buffers.normals = Some(vertex_normals);
+    }
+    buffers.materials = materials.to_vec();
+    Mesh::new(buffers)
+}
+
+struct MtlMaterial {
+    diffuse: Color,
+    specular: Option<[Float; 3]>,
+    shininess: Float,
+    dissolve: Float,
+    refraction: Float,
+    emission: [Float; 3],
+}
+
+impl MtlMaterial {
+    fn new() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: None,
+            shininess: 0.0,
+            dissolve: 1.0,
+            refraction: 1.0,
+            emission: [0.0, 0.0, 0.0],
+        }
+    }
+
+    // Kd is the color, Ks the color and Ns the sharpness of the reflection, d or Tr the
+    // transparency, Ni the refraction coefficient and Ke the emitted light
+    fn to_material(&self) -> Material {
+        let surface = match self.specular {
+            Some([r, g, b]) if r.max(g).max(b) > 0.0 => {
+                let strength = r.max(g).max(b);
+                // roughness of the blinn-phong lobe with exponent Ns
+                let roughness = (2.0 / (self.shininess + 2.0)).sqrt();
+                let glossy = Material::new(
+                    Color::new(r / strength, g / strength, b / strength),
+                    1.0,
+                    roughness,
+                    0.0,
+                    1.0,
+                    0.0,
+                );
+                let diffuse = Material::new(self.diffuse, 1.0, 1.0, 0.0, 1.0, 0.0);
+                Material {
+                    // the highlight of the ray tracer
+                    phong: Phong {
+                        ks: strength,
+                        shininess: self.shininess,
+                        specular: Specular::BlinnPhong,
+                        ..Phong::default()
+                    },
+                    ..Material::mix(&diffuse, &glossy, strength)
+                }
+            }
+            _ => Material::new_diffuse(self.diffuse),
+        };
+        let mut material = if self.dissolve < 1.0 {
+            let glass = Material::new_transparent(self.diffuse, 1.0, Medium::new(self.refraction));
+            Material::mix(&surface, &glass, 1.0 - self.dissolve)
+        } else {
+            surface
+        };
+        // Kd stays the albedo of emitters, only the emitted light has the color of Ke
+        let [r, g, b] = self.emission;
+        let light = r.max(g).max(b);
+        if light > 0.0 {
+            material.color = Color::new(r / light, g / light, b / light);
+            material.light = light;
+        }
+        material
+    }
+}
+
+pub fn parse_mtl(text: &str, file: &str) -> Result<HashMap<String, Material>, ObjError> {
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+    for (number, line) in text.lines().enumerate() {
+        let mut parser = LineParser::new(line, file, number + 1);
+        let keyword = match parser.next() {
+            None => continue,
+            Some(keyword) => keyword,
+        };
+        if keyword == "newmtl" {
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material.to_material());
+            }
+            current = Some((parser.rest().to_string(), MtlMaterial::new()));
+            continue;
+        }
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None => return Err(parser.error("material property before newmtl")),
+        };
+        match keyword {
+            "Kd" => {
+                let [r, g, b] = parser.rgb()?;
+                material.diffuse = Color::new(r, g, b);
+            }
+            "Ks" => material.specular = Some(parser.rgb()?),
+            "Ns" => material.shininess = parser.float()?,
+            "d" => material.dissolve = parser.float()?,
+            "Tr" => material.dissolve = 1.0 - parser.float()?,
+            "Ni" => material.refraction = parser.float()?,
+            "Ke" => material.emission = parser.rgb()?,
+            // ambient color, illumination model, texture maps and so on are ignored
+            _ => {}
+        }
+    }
+    if let Some((name, material)) = current {
+        materials.insert(name, material.to_material());
+    }
+    Ok(materials)
+}
+
+struct LineParser<'a> {
+    line: &'a str,
+    words: SplitWhitespace<'a>,
+    file: &'a str,
+    number: usize,
+}
+
+impl<'a> LineParser<'a> {
+    fn new(line: &'a str, file: &'a str, number: usize) -> Self {
+        let line = match line.find('#') {
+            Some(comment) => &line[..comment],
+            None => line,
+        };
+        Self {
+            line,
+            words: line.split_whitespace(),
+            file,
+            number,
+        }
+    }
+
+    fn error(&self, message: &str) -> ObjError {
+        ObjError::Parse {
+            file: self.file.to_string(),
+            line: self.number,
+            message: message.to_string(),
+        }
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.words.next()
+    }
+
+    // everything after the keyword, names may contain spaces
+    fn rest(&self) -> &'a str {
+        let line = self.line.trim();
+        match line.find(char::is_whitespace) {
+            Some(space) => line[space..].trim(),
+            None => "",
+        }
+    }
+
+    fn optional_float(&mut self) -> Result<Option<Float>, ObjError> {
+        match self.next() {
+            None => Ok(None),
+            Some(word) => match word.parse() {
+                Ok(value) => Ok(Some(value)),
+                Err(_) => Err(self.error(&format!("expected a number, found '{}'", word))),
+            },
+        }
+    }
+
+    fn float(&mut self) -> Result<Float, ObjError> {
+        match self.optional_float()? {
+            Some(value) => Ok(value),
+            None => Err(self.error("expected a number")),
+        }
+    }
+
+    fn point(&mut self) -> Result<Point, ObjError> {
+        Ok(Point::new(self.float()?, self.float()?, self.float()?))
+    }
+
+    // a single value means a gray color
+    fn rgb(&mut self) -> Result<[Float; 3], ObjError> {
+        let r = self.float()?;
+        match self.optional_float()? {
+            None => Ok([r, r, r]),
+            Some(g) => Ok([r, g, self.float()?]),
+        }
+    }
+
+    // v, v/vt, v//vn or v/vt/vn, indices start at 1 and negative ones count from the end
+    fn face_vertex(
+        &self,
+        word: &str,
+        positions: usize,
+        uvs: usize,
+        normals: usize,
+    ) -> Result<FaceVertex, ObjError> {
+        let mut parts = word.split('/');
+        let position = self.index(parts.next().unwrap_or(""), positions, word)?;
+        let uv = match parts.next() {
+            None | Some("") => None,
+            Some(part) => Some(self.index(part, uvs, word)?),
+        };
+        let normal = match parts.next() {
+            None | Some("") => None,
+            Some(part) => Some(self.index(part, normals, word)?),
+        };
+        if parts.next().is_some() {
+            return Err(self.error(&format!("invalid face vertex '{}'", word)));
+        }
+        Ok((position, uv, normal))
+    }
+
+    fn index(&self, part: &str, count: usize, word: &str) -> Result<usize, ObjError> {
+        let index: i64 = match part.parse() {
+            Ok(index) => index,
+            Err(_) => return Err(self.error(&format!("invalid face vertex '{}'", word))),
+        };
+        let resolved = if index < 0 {
+            count as i64 + index
+        } else {
+            index - 1
+        };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(&format!("index {} in '{}' is out of range", index, word)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{bsdf::Hit, entities::IntersectionResult, world::World};
+
+    const MTL: &str = "\
+newmtl glass
+Kd 0.1 0.2 0.3
+Ks 1.0 1.0 1.0
+Ns 1000
+d 0.25
+Ni 1.5
+
+newmtl lamp
+Ke 4 2 0
+
+newmtl painted
+Kd 0.5 0.25 0.125
+Ks 0.5 0 0
+Ns 100
+Ke 2 2 1
+";
+
+    fn parse_with_mtl(text: &str) -> Result<Vec<(Entity, Material)>, ObjError> {
+        parse(
+            text,
+            "test.obj",
+            &ObjOptions::new(Material::new_diffuse(Color::WHITE)),
+            |name| {
+                assert_eq!(name, "test.mtl");
+                Ok(("test.mtl".to_string(), MTL.to_string()))
+            },
+        )
+    }
+
+    fn face_material<'a>(entity: &'a Entity, hit: &IntersectionResult) -> &'a Material {
+        match entity {
+            Entity::Mesh(mesh) => &mesh.materials()[hit.material.unwrap()],
+            _ => panic!("obj files are loaded as meshes"),
+        }
+    }
+
+    #[test]
+    fn mtl_maps_onto_material() {
+        let materials = parse_mtl(MTL, "test.mtl").unwrap();
+        let glass = &materials["glass"];
+        // head on, a quarter is reflected by the specular lobe and the rest is glass tinted by Kd
+        let hit = Hit::new(
+            Point::new(0.0, 0.0, 1.0),
+            Point::new(0.0, 0.0, 1.0),
+            1.0,
+            glass.refraction_coefficient(),
+        );
+        let refracted: Float = glass
+            .bsdf
+            .specular(&hit)
+            .iter()
+            .filter(|lobe| lobe.refracted)
+            .map(|lobe| lobe.weight.channels()[0])
+            .sum();
+        assert!((refracted - 0.1 * 0.75 * 0.96).abs() < 1e-12);
+        assert_eq!(glass.bsdf.albedo(&hit), Color::BLACK);
+        assert_eq!(glass.refraction_coefficient(), 1.5);
+        assert_eq!(glass.light, 0.0);
+        assert_eq!(glass.phong.shininess, 1000.0);
+        assert_eq!(glass.phong.specular, Specular::BlinnPhong);
+        let lamp = &materials["lamp"];
+        assert_eq!(lamp.light, 4.0);
+        assert!(lamp.medium.is_none());
+        assert_eq!(lamp.phong, Phong::default());
+    }
+
+    #[test]
+    fn diffuse_specular_and_emitted_colors_are_kept_apart() {
+        let materials = parse_mtl(MTL, "test.mtl").unwrap();
+        let painted = &materials["painted"];
+        assert_eq!(painted.color, Color::new(1.0, 1.0, 0.5));
+        assert_eq!(painted.light, 2.0);
+        let hit = Hit::new(
+            Point::new(0.0, 0.0, 1.0),
+            Point::new(0.0, 0.0, 1.0),
+            1.0,
+            1.0,
+        );
+        // half of the light is reflected diffusely with Kd, the other half by a red lobe
+        let [_, g, b] = painted.bsdf.albedo(&hit).channels();
+        assert_eq!([g, b], [0.25 * 0.5, 0.125 * 0.5]);
+        let [r, g, b] = painted.bsdf.specular(&hit)[0].weight.channels();
+        assert!(r > 0.0 && g == 0.0 && b == 0.0);
+        assert_eq!(painted.phong.ks, 0.5);
+    }
+
+    #[test]
+    fn polygons_groups_and_materials() {
+        let entities = parse_with_mtl(
+            "\
+mtllib test.mtl
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 -1
+g quad
+usemtl lamp
+f 1/1/1 2/2/1 3/3/1 4/4/1
+g triangle
+usemtl glass
+f -4 -3 -1
+",
+        )
+        .unwrap();
+        assert_eq!(entities.len(), 2);
+
+        let direction = Point::new(0.0, 0.0, 1.0);
+        let hit = entities[0]
+            .0
+            .intersect(Point::new(0.2, 0.7, 0.0), direction)
+            .unwrap();
+        assert_eq!(face_material(&entities[0].0, &hit).light, 4.0);
+        let (u, v) = hit.uv.unwrap();
+        assert!((u - 0.2).abs() < 1e-9 && (v - 0.7).abs() < 1e-9);
+
+        let hit = entities[1]
+            .0
+            .intersect(Point::new(0.1, 0.5, 0.0), direction)
+            .unwrap();
+        assert_eq!(
+            face_material(&entities[1].0, &hit).refraction_coefficient(),
+            1.5
+        );
+        assert!(entities[1]
+            .0
+            .intersect(Point::new(0.9, 0.5, 0.0), direction)
+            .is_none());
+    }
+
+    #[test]
+    fn emissive_groups_are_emitters() {
+        let entities = parse_with_mtl(
+            "\
+mtllib test.mtl
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+g lamp
+usemtl lamp
+f 1 2 3
+g glass
+usemtl glass
+f 1 3 4
+",
+        )
+        .unwrap();
+        let world = World::new(entities, vec![]);
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let sample = world.sample_emitter(&mut rng).unwrap();
+            assert!(sample.point.x >= sample.point.y - 1e-9);
+            assert_eq!(sample.radiance, Color::new(4.0, 2.0, 0.0));
+        }
+    }
+
+    #[test]
+    fn errors_have_line_numbers() {
+        let error = parse_with_mtl("v 0 0 0\nv 1 0 0\nf 1 2 3\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "test.obj:3: index 3 in '3' is out of range"
+        );
+        let error = parse_with_mtl("v 0 0 0\nv 1 zero 0\n").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "test.obj:2: expected a number, found 'zero'"
+        );
+        let error = parse_with_mtl("mtllib test.mtl\nusemtl steel\n")
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "test.obj:2: unknown material 'steel'");
+        let error = parse_mtl("newmtl a\nKd 1 1\n", "test.mtl").err().unwrap();
+        assert_eq!(error.to_string(), "test.mtl:2: expected a number");
+    }
+}
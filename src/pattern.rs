@@ -0,0 +1,164 @@
+use crate::{
+    drawing::Color,
+    geometry::Point,
+    noise::Noise,
+    texture::{Procedural, Texture, TexturePoint},
+    Float,
+};
+
+// solid patterns fill space, so entities look carved out of them, surface patterns follow the
+// texture coordinates of the entity like an image, with (u, v, 0) as the point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coordinates {
+    Solid,
+    Surface,
+}
+
+// how far a point is from the first texture of a pattern towards the second one, in [0, 1]
+#[derive(Clone, Copy, Debug)]
+pub enum Pattern {
+    // alternating unit cubes, or squares on the surface
+    Checker,
+    // 0 at start and 1 at end, constant across the line between them
+    Gradient { start: Point, end: Point },
+    Perlin { octaves: usize },
+    Turbulence { octaves: usize },
+    // stripes across x bent by turbulence
+    Marble { octaves: usize, strength: Float },
+    // rings around the y axis, one per unit, disturbed by noise
+    Wood { strength: Float },
+    // distance to the closest of randomly placed points, dark spots in lighter cells
+    Worley,
+}
+
+pub struct PatternTexture {
+    pattern: Pattern,
+    first: Texture,
+    second: Texture,
+    noise: Noise,
+    scale: Float,
+    coordinates: Coordinates,
+}
+
+impl PatternTexture {
+    pub fn new(pattern: Pattern, first: Texture, second: Texture) -> Self {
+        Self {
+            pattern,
+            first,
+            second,
+            noise: Noise::new(0),
+            scale: 1.0,
+            coordinates: Coordinates::Solid,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.noise = Noise::new(seed);
+        self
+    }
+
+    // repetitions of the pattern per unit of distance
+    pub fn with_scale(mut self, scale: Float) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_coordinates(mut self, coordinates: Coordinates) -> Self {
+        self.coordinates = coordinates;
+        self
+    }
+
+    pub fn amount(&self, at: &TexturePoint) -> Float {
+        let point = match self.coordinates {
+            Coordinates::Solid => at.point,
+            Coordinates::Surface => Point::new(at.uv.0, at.uv.1, 0.0),
+        } * self.scale;
+        let amount = match self.pattern {
+            Pattern::Checker => {
+                let sum = point.x.floor() + point.y.floor() + point.z.floor();
+                sum.rem_euclid(2.0)
+            }
+            Pattern::Gradient { start, end } => {
+                let axis = end - start;
+                (point - start) * axis / (axis * axis)
+            }
+            Pattern::Perlin { octaves } => 0.5 + 0.5 * self.noise.fbm(point, octaves),
+            Pattern::Turbulence { octaves } => self.noise.turbulence(point, octaves),
+            Pattern::Marble { octaves, strength } => {
+                let turbulence = self.noise.turbulence(point, octaves);
+                0.5 + 0.5 * (point.x + strength * turbulence).sin()
+            }
+            Pattern::Wood { strength } => {
+                let radius = (point.x * point.x + point.z * point.z).sqrt();
+                (radius + strength * self.noise.perlin(point)).rem_euclid(1.0)
+            }
+            Pattern::Worley => self.noise.worley(point),
+        };
+        amount.clamp(0.0, 1.0)
+    }
+}
+
+impl Procedural for PatternTexture {
+    fn value(&self, at: &TexturePoint) -> Color {
+        let amount = self.amount(at);
+        self.first.value(at) * (1.0 - amount) + self.second.value(at) * amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: Float, y: Float, z: Float) -> TexturePoint {
+        TexturePoint::new(Point::new(x, y, z), (x, y))
+    }
+
+    fn pattern(pattern: Pattern) -> PatternTexture {
+        PatternTexture::new(pattern, Color::BLACK.into(), Color::WHITE.into())
+    }
+
+    #[test]
+    fn checker_alternates() {
+        let checker = pattern(Pattern::Checker).with_scale(2.0);
+        assert_eq!(checker.value(&at(0.25, 0.25, 0.25)), Color::BLACK);
+        assert_eq!(checker.value(&at(0.75, 0.25, 0.25)), Color::WHITE);
+        assert_eq!(checker.value(&at(-0.25, 0.25, 0.25)), Color::WHITE);
+        let surface = checker.with_coordinates(Coordinates::Surface);
+        assert_eq!(surface.value(&at(0.75, 0.75, 0.75)), Color::BLACK);
+    }
+
+    #[test]
+    fn gradient_between_start_and_end() {
+        let gradient = pattern(Pattern::Gradient {
+            start: Point::new(0.0, 0.0, 0.0),
+            end: Point::new(0.0, 2.0, 0.0),
+        });
+        assert_eq!(gradient.amount(&at(5.0, 1.0, -3.0)), 0.5);
+        assert_eq!(gradient.amount(&at(0.0, -1.0, 0.0)), 0.0);
+        assert_eq!(gradient.amount(&at(0.0, 3.0, 0.0)), 1.0);
+    }
+
+    #[test]
+    fn noise_patterns_stay_in_range() {
+        let patterns = [
+            Pattern::Perlin { octaves: 4 },
+            Pattern::Turbulence { octaves: 4 },
+            Pattern::Marble {
+                octaves: 4,
+                strength: 5.0,
+            },
+            Pattern::Wood { strength: 0.5 },
+            Pattern::Worley,
+        ];
+        for p in patterns.iter() {
+            let texture = pattern(*p).with_seed(9).with_scale(3.0);
+            let same = pattern(*p).with_seed(9).with_scale(3.0);
+            for i in 0..50 {
+                let point = at(i as Float * 0.31, 1.7 - i as Float * 0.05, 0.2);
+                let amount = texture.amount(&point);
+                assert!((0.0..=1.0).contains(&amount));
+                assert_eq!(amount, same.amount(&point));
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::f64::consts::PI;
+
+use rand::Rng;
+
+use crate::{geometry::Point, Float};
+
+// direction in the hemisphere around normal with density cos(theta) / pi
+pub fn cosine_hemisphere<R: Rng + ?Sized>(normal: Point, rng: &mut R) -> Point {
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let phi = 2.0 * PI * rng.gen::<Float>();
+    let r2 = rng.gen::<Float>();
+    let r = r2.sqrt();
+    tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * (1.0 - r2).sqrt()
+}
+
+// normal flipped to the side of the surface the ray came from
+pub fn facing_normal(direction: Point, normal: Point) -> Point {
+    let normal = normal.normalize();
+    if direction * normal > 0.0 {
+        normal * -1.0
+    } else {
+        normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    #[test]
+    fn cosine_hemisphere_distribution() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let normal = Point::new(1.0, -2.0, 0.5).normalize();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let samples = 200_000;
+        let bins = 10;
+        let mut histogram = vec![0usize; bins];
+        let mut mean = Point::new(0.0, 0.0, 0.0);
+        for _ in 0..samples {
+            let direction = cosine_hemisphere(normal, &mut rng);
+            assert!((direction.len() - 1.0).abs() < 1e-9);
+            let cos = direction * normal;
+            assert!(cos >= 0.0);
+            // for p(w) = cos / pi the squared cosine is uniform on [0, 1]
+            histogram[((cos * cos * bins as Float) as usize).min(bins - 1)] += 1;
+            mean += direction;
+        }
+
+        let expected = samples as Float / bins as Float;
+        let chi_square: Float = histogram
+            .iter()
+            .map(|&count| (count as Float - expected).powi(2) / expected)
+            .sum();
+        // 99.9% quantile of chi-square with 9 degrees of freedom
+        assert!(chi_square < 27.88, "chi-square {}", chi_square);
+
+        // E[cos] = 2 / 3 and the azimuth is uniform
+        let mean = mean / samples as Float;
+        assert!((mean * normal - 2.0 / 3.0).abs() < 0.005);
+        assert!((mean * tangent).abs() < 0.005);
+        assert!((mean * bitangent).abs() < 0.005);
+    }
+}
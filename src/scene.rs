@@ -0,0 +1,1463 @@
+use std::{collections::HashMap, convert::TryFrom, fmt, fs, path::Path, sync::Arc};
+
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{
+    bump::Bump,
+    camera::Camera,
+    drawing::Color,
+    entities::{Entity, Mesh, MeshBuffers, Plane, Sphere, Triangle},
+    film::FilmError,
+    geometry::Point,
+    light::Light,
+    material::{Material, Phong, Specular},
+    medium::Medium,
+    microfacet::Fresnel,
+    obj::{self, ObjError, ObjOptions},
+    pattern::{Coordinates, Pattern, PatternTexture},
+    shapes,
+    texture::{ImageTexture, Texture, Wrap},
+    world::{Ambient, World},
+    Float,
+};
+
+// scene file format, see scenes/*.toml for examples
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDescription {
+    #[serde(default)]
+    render: RenderSettings,
+    #[serde(default)]
+    camera: Option<CameraDescription>,
+    #[serde(default)]
+    lights: Vec<LightDescription>,
+    #[serde(default)]
+    ambient: AmbientDescription,
+    #[serde(default)]
+    materials: HashMap<String, MaterialDescription>,
+    #[serde(default)]
+    entities: Vec<EntityDescription>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RenderSettings {
+    #[serde(default = "default_size")]
+    pub width: usize,
+    #[serde(default = "default_size")]
+    pub height: usize,
+    // samples per pixel of the path tracer, unbounded if not set
+    #[serde(default)]
+    pub samples: Option<usize>,
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+}
+
+fn default_scale() -> Float {
+    1.0
+}
+
+fn default_size() -> usize {
+    500
+}
+
+fn default_max_depth() -> usize {
+    10
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            width: default_size(),
+            height: default_size(),
+            samples: None,
+            max_depth: default_max_depth(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDescription {
+    eye: [Float; 3],
+    target: [Float; 3],
+    #[serde(default = "default_up")]
+    up: [Float; 3],
+    // vertical field of view in degrees
+    #[serde(default = "default_fov")]
+    fov: Float,
+    #[serde(default)]
+    aspect_ratio: Option<Float>,
+}
+
+// y points down on the image, so that scenes place ceilings at negative y
+fn default_up() -> [Float; 3] {
+    [0.0, -1.0, 0.0]
+}
+
+fn default_fov() -> Float {
+    90.0
+}
+
+impl CameraDescription {
+    fn to_camera(&self) -> Result<Camera, SceneError> {
+        let camera = Camera::new(
+            point(self.eye),
+            point(self.target),
+            point(self.up),
+            self.fov.to_radians(),
+        )
+        .map_err(SceneError::InvalidCamera)?;
+        Ok(match self.aspect_ratio {
+            Some(aspect_ratio) => camera.with_aspect_ratio(aspect_ratio),
+            None => camera,
+        })
+    }
+}
+
+// integer arrays are bytes and arrays with a fraction are floats, integer arrays of zeros and ones
+// could mean either, so they are rejected
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "RawColorDescription")]
+enum ColorDescription {
+    Bytes([u8; 3]),
+    Floats([Float; 3]),
+    Named(NamedColor),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawColorDescription {
+    Components([Component; 3]),
+    Named(NamedColor),
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum Component {
+    Integer(i64),
+    Float(Float),
+}
+
+impl TryFrom<RawColorDescription> for ColorDescription {
+    type Error = String;
+
+    fn try_from(raw: RawColorDescription) -> Result<Self, String> {
+        let components = match raw {
+            RawColorDescription::Named(name) => return Ok(ColorDescription::Named(name)),
+            RawColorDescription::Components(components) => components,
+        };
+        let integers: Vec<i64> = components
+            .iter()
+            .filter_map(|component| match component {
+                Component::Integer(integer) => Some(*integer),
+                Component::Float(_) => None,
+            })
+            .collect();
+        if integers.len() < 3 {
+            let float = |component: Component| match component {
+                Component::Integer(integer) => integer as Float,
+                Component::Float(float) => float,
+            };
+            let [r, g, b] = components;
+            return Ok(ColorDescription::Floats([float(r), float(g), float(b)]));
+        }
+        if integers.iter().any(|integer| !(0..=255).contains(integer)) {
+            return Err(format!(
+                "byte color {:?} has components outside of 0 to 255",
+                integers
+            ));
+        }
+        if integers.iter().all(|&integer| integer <= 1) && integers.contains(&1) {
+            return Err(format!(
+                "color {:?} could be bytes or floats, write floats with a fraction like 1.0",
+                integers
+            ));
+        }
+        Ok(ColorDescription::Bytes([
+            integers[0] as u8,
+            integers[1] as u8,
+            integers[2] as u8,
+        ]))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum NamedColor {
+    White,
+    Black,
+    Red,
+    Green,
+    Blue,
+    Orange,
+    Purple,
+    Brown,
+    Cyan,
+    DarkGreen,
+    Moccasin,
+    Gold,
+    Magenta,
+}
+
+impl ColorDescription {
+    fn to_color(self) -> Color {
+        match self {
+            ColorDescription::Bytes([r, g, b]) => Color::from_rgb(r, g, b),
+            ColorDescription::Floats([r, g, b]) => Color::new(r, g, b),
+            ColorDescription::Named(name) => match name {
+                NamedColor::White => Color::WHITE,
+                NamedColor::Black => Color::BLACK,
+                NamedColor::Red => Color::RED,
+                NamedColor::Green => Color::GREEN,
+                NamedColor::Blue => Color::BLUE,
+                NamedColor::Orange => Color::ORANGE,
+                NamedColor::Purple => Color::PURPLE,
+                NamedColor::Brown => Color::BROWN,
+                NamedColor::Cyan => Color::CYAN,
+                NamedColor::DarkGreen => Color::DARK_GREEN,
+                NamedColor::Moccasin => Color::MOCCASIN,
+                NamedColor::Gold => Color::GOLD,
+                NamedColor::Magenta => Color::MAGENTA,
+            },
+        }
+    }
+}
+
+// a color, a gray value for coefficients, or a table with a type: an image over the texture
+// coordinates of the entity or a procedural pattern between two textures
+#[derive(Clone)]
+enum TextureDescription {
+    Color(ColorDescription),
+    Gray(Float),
+    Image(ImageDescription),
+    Pattern {
+        pattern: PatternDescription,
+        texture: PatternTextureDescription,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct ImageDescription {
+    // relative to the scene file
+    path: String,
+    #[serde(default)]
+    wrap: WrapDescription,
+    // 8 bit images that are not srgb, like roughness maps
+    #[serde(default)]
+    linear: bool,
+    // copies of the image per unit of texture coordinates
+    #[serde(default = "default_texture_scale")]
+    scale: [Float; 2],
+}
+
+// the keys every pattern has, the others belong to the kind of pattern
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct PatternTextureDescription {
+    #[serde(default = "default_first")]
+    first: Box<TextureDescription>,
+    #[serde(default = "default_second")]
+    second: Box<TextureDescription>,
+    #[serde(default)]
+    seed: u64,
+    // repetitions of the pattern per unit of distance
+    #[serde(default = "default_scale")]
+    scale: Float,
+    #[serde(default)]
+    coordinates: CoordinatesDescription,
+}
+
+const PATTERN_TEXTURE_KEYS: [&str; 5] = ["first", "second", "seed", "scale", "coordinates"];
+
+impl<'de> Deserialize<'de> for TextureDescription {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut table = match toml::Value::deserialize(deserializer)? {
+            toml::Value::Float(gray) => return Ok(TextureDescription::Gray(gray)),
+            toml::Value::Integer(gray) => return Ok(TextureDescription::Gray(gray as Float)),
+            toml::Value::Table(table) => table,
+            value => {
+                return ColorDescription::deserialize(value)
+                    .map(TextureDescription::Color)
+                    .map_err(de::Error::custom)
+            }
+        };
+        let kind = match table.remove("type") {
+            Some(toml::Value::String(kind)) => kind,
+            _ => {
+                return Err(de::Error::custom(
+                    "texture tables need a type, image or pattern",
+                ))
+            }
+        };
+        match kind.as_str() {
+            "image" => ImageDescription::deserialize(toml::Value::Table(table))
+                .map(TextureDescription::Image)
+                .map_err(de::Error::custom),
+            "pattern" => {
+                let mut pattern = toml::value::Table::new();
+                let keys: Vec<String> = table
+                    .keys()
+                    .filter(|key| !PATTERN_TEXTURE_KEYS.contains(&key.as_str()))
+                    .cloned()
+                    .collect();
+                for key in keys {
+                    let value = table.remove(&key).expect("the key is in the table");
+                    pattern.insert(key, value);
+                }
+                Ok(TextureDescription::Pattern {
+                    pattern: PatternDescription::deserialize(toml::Value::Table(pattern))
+                        .map_err(de::Error::custom)?,
+                    texture: PatternTextureDescription::deserialize(toml::Value::Table(table))
+                        .map_err(de::Error::custom)?,
+                })
+            }
+            other => Err(de::Error::custom(format!(
+                "unknown texture type '{}', expected image or pattern",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(tag = "pattern", rename_all = "snake_case", deny_unknown_fields)]
+enum PatternDescription {
+    Checker,
+    Gradient {
+        start: [Float; 3],
+        end: [Float; 3],
+    },
+    Perlin {
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+    },
+    Turbulence {
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+    },
+    Marble {
+        #[serde(default = "default_octaves")]
+        octaves: usize,
+        #[serde(default = "default_marble_strength")]
+        strength: Float,
+    },
+    Wood {
+        #[serde(default = "default_wood_strength")]
+        strength: Float,
+    },
+    Worley,
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum CoordinatesDescription {
+    #[default]
+    Solid,
+    Surface,
+}
+
+fn default_first() -> Box<TextureDescription> {
+    Box::new(TextureDescription::Color(ColorDescription::Named(
+        NamedColor::Black,
+    )))
+}
+
+fn default_second() -> Box<TextureDescription> {
+    Box::new(TextureDescription::Color(ColorDescription::Named(
+        NamedColor::White,
+    )))
+}
+
+fn default_octaves() -> usize {
+    6
+}
+
+fn default_marble_strength() -> Float {
+    5.0
+}
+
+fn default_wood_strength() -> Float {
+    0.5
+}
+
+impl PatternDescription {
+    fn to_pattern(self) -> Pattern {
+        match self {
+            PatternDescription::Checker => Pattern::Checker,
+            PatternDescription::Gradient { start, end } => Pattern::Gradient {
+                start: point(start),
+                end: point(end),
+            },
+            PatternDescription::Perlin { octaves } => Pattern::Perlin { octaves },
+            PatternDescription::Turbulence { octaves } => Pattern::Turbulence { octaves },
+            PatternDescription::Marble { octaves, strength } => {
+                Pattern::Marble { octaves, strength }
+            }
+            PatternDescription::Wood { strength } => Pattern::Wood { strength },
+            PatternDescription::Worley => Pattern::Worley,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+enum WrapDescription {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+fn default_texture_scale() -> [Float; 2] {
+    [1.0, 1.0]
+}
+
+impl TextureDescription {
+    fn to_texture(&self, directory: &Path) -> Result<Texture, SceneError> {
+        match self {
+            TextureDescription::Color(color) => Ok(color.to_color().into()),
+            TextureDescription::Gray(value) => Ok((*value).into()),
+            TextureDescription::Image(ImageDescription {
+                path,
+                wrap,
+                linear,
+                scale: [u, v],
+            }) => {
+                let wrap = match wrap {
+                    WrapDescription::Repeat => Wrap::Repeat,
+                    WrapDescription::Clamp => Wrap::Clamp,
+                    WrapDescription::Mirror => Wrap::Mirror,
+                };
+                let texture = ImageTexture::open(directory.join(path), wrap, *linear)
+                    .map_err(|error| SceneError::Texture(path.clone(), error))?;
+                Ok(Texture::Image(Arc::new(texture.with_scale(*u, *v))))
+            }
+            TextureDescription::Pattern {
+                pattern,
+                texture:
+                    PatternTextureDescription {
+                        first,
+                        second,
+                        seed,
+                        scale,
+                        coordinates,
+                    },
+            } => {
+                let coordinates = match coordinates {
+                    CoordinatesDescription::Solid => Coordinates::Solid,
+                    CoordinatesDescription::Surface => Coordinates::Surface,
+                };
+                let texture = PatternTexture::new(
+                    pattern.to_pattern(),
+                    first.to_texture(directory)?,
+                    second.to_texture(directory)?,
+                )
+                .with_seed(*seed)
+                .with_scale(*scale)
+                .with_coordinates(coordinates);
+                Ok(Texture::Procedural(Arc::new(texture)))
+            }
+        }
+    }
+}
+
+// angles are in degrees
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum LightDescription {
+    Point {
+        position: [Float; 3],
+        #[serde(default = "default_light_color")]
+        color: ColorDescription,
+        #[serde(default = "default_intensity")]
+        intensity: Float,
+    },
+    Directional {
+        direction: [Float; 3],
+        #[serde(default = "default_light_color")]
+        color: ColorDescription,
+        #[serde(default = "default_intensity")]
+        intensity: Float,
+    },
+    Spot {
+        position: [Float; 3],
+        direction: [Float; 3],
+        inner_angle: Float,
+        outer_angle: Float,
+        #[serde(default = "default_falloff")]
+        falloff: Float,
+        #[serde(default = "default_light_color")]
+        color: ColorDescription,
+        #[serde(default = "default_intensity")]
+        intensity: Float,
+    },
+    Rectangle {
+        corner: [Float; 3],
+        u: [Float; 3],
+        v: [Float; 3],
+        #[serde(default = "default_light_color")]
+        color: ColorDescription,
+        #[serde(default = "default_intensity")]
+        intensity: Float,
+    },
+    Disk {
+        center: [Float; 3],
+        normal: [Float; 3],
+        radius: Float,
+        #[serde(default = "default_light_color")]
+        color: ColorDescription,
+        #[serde(default = "default_intensity")]
+        intensity: Float,
+    },
+    Sphere {
+        center: [Float; 3],
+        radius: Float,
+        #[serde(default = "default_light_color")]
+        color: ColorDescription,
+        #[serde(default = "default_intensity")]
+        intensity: Float,
+    },
+}
+
+fn default_light_color() -> ColorDescription {
+    ColorDescription::Named(NamedColor::White)
+}
+
+fn default_intensity() -> Float {
+    1.0
+}
+
+// the cone fades linearly in the cosine between the angles
+fn default_falloff() -> Float {
+    1.0
+}
+
+impl LightDescription {
+    fn to_light(&self) -> Result<Light, SceneError> {
+        Ok(match *self {
+            LightDescription::Point {
+                position,
+                color,
+                intensity,
+            } => Light::Point {
+                position: point(position),
+                color: color.to_color(),
+                intensity,
+            },
+            LightDescription::Directional {
+                direction,
+                color,
+                intensity,
+            } => Light::Directional {
+                direction: point(direction),
+                color: color.to_color(),
+                intensity,
+            },
+            LightDescription::Spot {
+                position,
+                direction,
+                inner_angle,
+                outer_angle,
+                falloff,
+                color,
+                intensity,
+            } => {
+                if !(inner_angle > 0.0 && inner_angle <= outer_angle && outer_angle <= 180.0) {
+                    return Err(SceneError::InvalidLight(format!(
+                        "spot angles {} and {} have to satisfy 0 < inner <= outer <= 180",
+                        inner_angle, outer_angle
+                    )));
+                }
+                Light::Spot {
+                    position: point(position),
+                    direction: point(direction),
+                    inner_angle: inner_angle.to_radians(),
+                    outer_angle: outer_angle.to_radians(),
+                    falloff,
+                    color: color.to_color(),
+                    intensity,
+                }
+            }
+            LightDescription::Rectangle {
+                corner,
+                u,
+                v,
+                color,
+                intensity,
+            } => Light::Rectangle {
+                corner: point(corner),
+                u: point(u),
+                v: point(v),
+                color: color.to_color(),
+                intensity,
+            },
+            LightDescription::Disk {
+                center,
+                normal,
+                radius,
+                color,
+                intensity,
+            } => Light::Disk {
+                center: point(center),
+                normal: point(normal),
+                radius,
+                color: color.to_color(),
+                intensity,
+            },
+            LightDescription::Sphere {
+                center,
+                radius,
+                color,
+                intensity,
+            } => Light::Sphere {
+                center: point(center),
+                radius,
+                color: color.to_color(),
+                intensity,
+            },
+        })
+    }
+}
+
+// ambient light of the ray tracer
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct AmbientDescription {
+    color: ColorDescription,
+    intensity: Float,
+}
+
+impl Default for AmbientDescription {
+    fn default() -> Self {
+        Self {
+            color: ColorDescription::Named(NamedColor::White),
+            intensity: Ambient::default().intensity,
+        }
+    }
+}
+
+impl AmbientDescription {
+    fn to_ambient(&self) -> Ambient {
+        Ambient {
+            color: self.color.to_color(),
+            intensity: self.intensity,
+        }
+    }
+}
+
+// shading of the ray tracer, every coefficient that is left out keeps its default
+#[derive(Deserialize, Clone, Copy)]
+#[serde(default, deny_unknown_fields)]
+struct PhongDescription {
+    ka: Float,
+    kd: Float,
+    ks: Float,
+    shininess: Float,
+    specular: SpecularDescription,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum SpecularDescription {
+    Phong,
+    BlinnPhong,
+}
+
+impl Default for PhongDescription {
+    fn default() -> Self {
+        let phong = Phong::default();
+        Self {
+            ka: phong.ka,
+            kd: phong.kd,
+            ks: phong.ks,
+            shininess: phong.shininess,
+            specular: SpecularDescription::Phong,
+        }
+    }
+}
+
+impl PhongDescription {
+    fn to_phong(self) -> Phong {
+        Phong {
+            ka: self.ka,
+            kd: self.kd,
+            ks: self.ks,
+            shininess: self.shininess,
+            specular: match self.specular {
+                SpecularDescription::Phong => Specular::Phong,
+                SpecularDescription::BlinnPhong => Specular::BlinnPhong,
+            },
+        }
+    }
+}
+
+// bends the normals of a material, by the slope of a height texture scaled by strength or by the
+// colors of a normal map, normal map images need to be marked linear
+#[derive(Clone)]
+enum BumpDescription {
+    Height {
+        height: TextureDescription,
+        strength: Float,
+    },
+    Normal {
+        normal: TextureDescription,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct HeightDescription {
+    height: TextureDescription,
+    #[serde(default = "default_scale")]
+    strength: Float,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NormalDescription {
+    normal: TextureDescription,
+}
+
+// the key of the texture tells the kinds apart, so errors inside of it are not lost
+impl<'de> Deserialize<'de> for BumpDescription {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = toml::Value::deserialize(deserializer)?;
+        if value.get("normal").is_some() {
+            NormalDescription::deserialize(value)
+                .map(|NormalDescription { normal }| BumpDescription::Normal { normal })
+                .map_err(de::Error::custom)
+        } else {
+            HeightDescription::deserialize(value)
+                .map(
+                    |HeightDescription { height, strength }| BumpDescription::Height {
+                        height,
+                        strength,
+                    },
+                )
+                .map_err(de::Error::custom)
+        }
+    }
+}
+
+impl BumpDescription {
+    fn to_bump(&self, directory: &Path) -> Result<Bump, SceneError> {
+        Ok(match self {
+            BumpDescription::Height { height, strength } => Bump::Height {
+                height: height.to_texture(directory)?,
+                strength: *strength,
+            },
+            BumpDescription::Normal { normal } => Bump::Normal(normal.to_texture(directory)?),
+        })
+    }
+}
+
+// a metal preset, a complex index of refraction or the color at normal incidence
+#[derive(Deserialize, Clone, Copy)]
+#[serde(untagged)]
+enum FresnelDescription {
+    Metal(Metal),
+    Conductor { eta: [Float; 3], k: [Float; 3] },
+    Schlick { f0: ColorDescription },
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Metal {
+    Gold,
+    Copper,
+    Aluminium,
+}
+
+impl FresnelDescription {
+    fn to_fresnel(self) -> Fresnel {
+        match self {
+            FresnelDescription::Metal(Metal::Gold) => Fresnel::gold(),
+            FresnelDescription::Metal(Metal::Copper) => Fresnel::copper(),
+            FresnelDescription::Metal(Metal::Aluminium) => Fresnel::aluminium(),
+            FresnelDescription::Conductor {
+                eta: [eta_r, eta_g, eta_b],
+                k: [k_r, k_g, k_b],
+            } => Fresnel::Conductor {
+                eta: Color::new(eta_r, eta_g, eta_b),
+                k: Color::new(k_r, k_g, k_b),
+            },
+            FresnelDescription::Schlick { f0 } => Fresnel::Schlick(f0.to_color()),
+        }
+    }
+}
+
+fn default_tint() -> TextureDescription {
+    TextureDescription::Color(ColorDescription::Named(NamedColor::White))
+}
+
+fn default_coat_refraction() -> Float {
+    1.5
+}
+
+// each variant mirrors one of the Material constructors
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDescription {
+    Diffuse {
+        color: TextureDescription,
+        #[serde(default)]
+        phong: PhongDescription,
+        #[serde(default)]
+        bump: Option<BumpDescription>,
+    },
+    Mirror {
+        color: TextureDescription,
+        reflection: TextureDescription,
+        #[serde(default)]
+        phong: PhongDescription,
+        #[serde(default)]
+        bump: Option<BumpDescription>,
+    },
+    Transparent {
+        color: TextureDescription,
+        transparency: TextureDescription,
+        refraction_coefficient: Float,
+        // overlapping transparent entities: the one with the higher priority wins
+        #[serde(default)]
+        priority: u32,
+        // absorbed fraction per unit of distance inside for every channel
+        #[serde(default)]
+        absorption: [Float; 3],
+        #[serde(default)]
+        phong: PhongDescription,
+        #[serde(default)]
+        bump: Option<BumpDescription>,
+    },
+    Light {
+        color: ColorDescription,
+        light: Float,
+    },
+    // a rough metal, roughness 0 is a perfect mirror
+    Conductor {
+        fresnel: FresnelDescription,
+        roughness: TextureDescription,
+        #[serde(default = "default_tint")]
+        color: TextureDescription,
+        #[serde(default)]
+        phong: PhongDescription,
+        #[serde(default)]
+        bump: Option<BumpDescription>,
+    },
+    // frosted glass
+    RoughTransparent {
+        color: TextureDescription,
+        roughness: TextureDescription,
+        refraction_coefficient: Float,
+        #[serde(default)]
+        priority: u32,
+        #[serde(default)]
+        absorption: [Float; 3],
+        #[serde(default)]
+        phong: PhongDescription,
+        #[serde(default)]
+        bump: Option<BumpDescription>,
+    },
+    Custom {
+        color: TextureDescription,
+        reflection: TextureDescription,
+        diffuse: Float,
+        transparency: TextureDescription,
+        refraction_coefficient: Float,
+        light: Float,
+        #[serde(default)]
+        priority: u32,
+        #[serde(default)]
+        absorption: [Float; 3],
+        #[serde(default)]
+        phong: PhongDescription,
+        #[serde(default)]
+        bump: Option<BumpDescription>,
+    },
+    // weight is the fraction of the light scattered by second, the rest of the properties come
+    // from first
+    Mix {
+        first: Box<MaterialDescription>,
+        second: Box<MaterialDescription>,
+        weight: TextureDescription,
+    },
+    // base under a clear coat, like varnished wood or car paint
+    Coated {
+        base: Box<MaterialDescription>,
+        #[serde(default)]
+        roughness: Float,
+        #[serde(default = "default_coat_refraction")]
+        refraction_coefficient: Float,
+    },
+}
+
+impl MaterialDescription {
+    // textures are loaded from files relative to directory
+    fn to_material(&self, directory: &Path) -> Result<Material, SceneError> {
+        let texture = |description: &TextureDescription| description.to_texture(directory);
+        let to_bump = |bump: &Option<BumpDescription>| match bump {
+            None => Ok(None),
+            Some(bump) => bump.to_bump(directory).map(Some),
+        };
+        Ok(match self {
+            MaterialDescription::Diffuse { color, phong, bump } => Material {
+                phong: phong.to_phong(),
+                bump: to_bump(bump)?,
+                ..Material::new_diffuse(texture(color)?)
+            },
+            MaterialDescription::Mirror {
+                color,
+                reflection,
+                phong,
+                bump,
+            } => Material {
+                phong: phong.to_phong(),
+                bump: to_bump(bump)?,
+                ..Material::new_mirror(texture(color)?, texture(reflection)?)
+            },
+            MaterialDescription::Transparent {
+                color,
+                transparency,
+                refraction_coefficient,
+                priority,
+                absorption,
+                phong,
+                bump,
+            } => Material {
+                phong: phong.to_phong(),
+                bump: to_bump(bump)?,
+                ..Material::new_transparent(
+                    texture(color)?,
+                    texture(transparency)?,
+                    medium(*refraction_coefficient, *priority, *absorption),
+                )
+            },
+            MaterialDescription::Light { color, light } => {
+                Material::new_light(color.to_color(), *light)
+            }
+            MaterialDescription::Conductor {
+                fresnel,
+                roughness,
+                color,
+                phong,
+                bump,
+            } => Material {
+                phong: phong.to_phong(),
+                bump: to_bump(bump)?,
+                ..Material::new_conductor(fresnel.to_fresnel(), texture(roughness)?)
+                    .tinted(texture(color)?)
+            },
+            MaterialDescription::RoughTransparent {
+                color,
+                roughness,
+                refraction_coefficient,
+                priority,
+                absorption,
+                phong,
+                bump,
+            } => Material {
+                phong: phong.to_phong(),
+                bump: to_bump(bump)?,
+                ..Material::new_rough_transparent(
+                    texture(color)?,
+                    texture(roughness)?,
+                    medium(*refraction_coefficient, *priority, *absorption),
+                )
+            },
+            MaterialDescription::Custom {
+                color,
+                reflection,
+                diffuse,
+                transparency,
+                refraction_coefficient,
+                light,
+                priority,
+                absorption,
+                phong,
+                bump,
+            } => Material {
+                phong: phong.to_phong(),
+                bump: to_bump(bump)?,
+                ..Material::new_filled(
+                    texture(color)?,
+                    texture(reflection)?,
+                    *diffuse,
+                    texture(transparency)?,
+                    medium(*refraction_coefficient, *priority, *absorption),
+                    *light,
+                )
+            },
+            MaterialDescription::Mix {
+                first,
+                second,
+                weight,
+            } => Material::mix(
+                &first.to_material(directory)?,
+                &second.to_material(directory)?,
+                texture(weight)?,
+            ),
+            MaterialDescription::Coated {
+                base,
+                roughness,
+                refraction_coefficient,
+            } => Material::coated(
+                &base.to_material(directory)?,
+                *roughness,
+                *refraction_coefficient,
+            ),
+        })
+    }
+}
+
+// inside of a transparent material
+fn medium(refraction_coefficient: Float, priority: u32, [r, g, b]: [Float; 3]) -> Medium {
+    Medium {
+        priority,
+        absorption: Color::new(r, g, b),
+        ..Medium::new(refraction_coefficient)
+    }
+}
+
+// a material is either written inline or refers to an entry of the materials table
+enum MaterialReference {
+    Named(String),
+    Inline(Box<MaterialDescription>),
+}
+
+// strings are names and everything else is an inline material, whose errors are kept instead of
+// being lost like in an untagged enum
+impl<'de> Deserialize<'de> for MaterialReference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match toml::Value::deserialize(deserializer)? {
+            toml::Value::String(name) => Ok(MaterialReference::Named(name)),
+            value => MaterialDescription::deserialize(value)
+                .map(|description| MaterialReference::Inline(Box::new(description)))
+                .map_err(de::Error::custom),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum EntityDescription {
+    Sphere {
+        center: [Float; 3],
+        radius: Float,
+        #[serde(default)]
+        room: bool,
+        material: MaterialReference,
+    },
+    Triangle {
+        points: [[Float; 3]; 3],
+        #[serde(default)]
+        room: bool,
+        // texture coordinates of the points, the barycentric coordinates are used without them
+        #[serde(default)]
+        uvs: Option<[[Float; 2]; 3]>,
+        material: MaterialReference,
+    },
+    Plane {
+        points: [[Float; 3]; 3],
+        material: MaterialReference,
+    },
+    Cube {
+        origin: [Float; 3],
+        size: [Float; 3],
+        material: MaterialReference,
+    },
+    AbsoluteCube {
+        from: [Float; 3],
+        to: [Float; 3],
+        material: MaterialReference,
+    },
+    Room {
+        from: [Float; 3],
+        to: [Float; 3],
+        materials: [MaterialReference; 6],
+    },
+    GeneralCube {
+        from: [Float; 3],
+        to: [Float; 3],
+        materials: [MaterialReference; 6],
+        #[serde(default)]
+        reverse_normals: bool,
+    },
+    // wavefront obj file, the path is relative to the scene file
+    Obj {
+        path: String,
+        // used for faces without a material from the mtl library
+        material: MaterialReference,
+        #[serde(default = "default_scale")]
+        scale: Float,
+        #[serde(default)]
+        translate: [Float; 3],
+    },
+    // faces are wound so that their normals point inside, like those of the other entities
+    Mesh {
+        positions: Vec<[Float; 3]>,
+        faces: Vec<[usize; 3]>,
+        #[serde(default)]
+        normals: Option<Vec<[Float; 3]>>,
+        #[serde(default)]
+        uvs: Option<Vec<[Float; 2]>>,
+        material: MaterialReference,
+        // face groups: one index into materials per face
+        #[serde(default)]
+        materials: Vec<MaterialReference>,
+        #[serde(default)]
+        face_materials: Vec<usize>,
+    },
+}
+
+pub struct Scene {
+    pub world: World,
+    pub render: RenderSettings,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    UnknownMaterial(String),
+    InvalidMesh(String),
+    InvalidCamera(String),
+    InvalidLight(String),
+    Texture(String, FilmError),
+    Obj(ObjError),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(error) => write!(f, "could not read scene file: {}", error),
+            SceneError::Parse(error) => write!(f, "could not parse scene file: {}", error),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material '{}'", name),
+            SceneError::InvalidMesh(error) => write!(f, "invalid mesh: {}", error),
+            SceneError::InvalidCamera(error) => write!(f, "invalid camera: {}", error),
+            SceneError::InvalidLight(error) => write!(f, "invalid light: {}", error),
+            SceneError::Texture(path, error) => {
+                write!(f, "could not load texture '{}': {}", path, error)
+            }
+            SceneError::Obj(error) => write!(f, "could not load obj file: {}", error),
+        }
+    }
+}
+
+impl From<std::io::Error> for SceneError {
+    fn from(error: std::io::Error) -> Self {
+        SceneError::Io(error)
+    }
+}
+
+impl From<ObjError> for SceneError {
+    fn from(error: ObjError) -> Self {
+        SceneError::Obj(error)
+    }
+}
+
+impl From<toml::de::Error> for SceneError {
+    fn from(error: toml::de::Error) -> Self {
+        SceneError::Parse(error)
+    }
+}
+
+const BUILTIN: [(&str, &str); 4] = [
+    ("scene_1", include_str!("../scenes/scene_1.toml")),
+    ("scene_2", include_str!("../scenes/scene_2.toml")),
+    ("scene_3", include_str!("../scenes/scene_3.toml")),
+    ("scene_4", include_str!("../scenes/scene_4.toml")),
+];
+
+pub fn builtin_names() -> Vec<&'static str> {
+    BUILTIN.iter().map(|(name, _)| *name).collect()
+}
+
+// loads one of the scenes shipped with the binary or, if there is no such scene, a scene file
+pub fn load_by_name(name: &str) -> Result<Scene, SceneError> {
+    match BUILTIN.iter().find(|(builtin, _)| *builtin == name) {
+        Some((_, text)) => parse(text, Path::new("")),
+        None => load(name),
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    parse(
+        &fs::read_to_string(path)?,
+        path.parent().unwrap_or_else(|| Path::new("")),
+    )
+}
+
+// files referenced by the scene are looked up relative to directory
+pub fn parse(text: &str, directory: &Path) -> Result<Scene, SceneError> {
+    let description: SceneDescription = toml::from_str(text)?;
+
+    // named materials are built once, so their textures are only loaded once
+    let mut materials = HashMap::new();
+    for (name, material) in description.materials.iter() {
+        materials.insert(name.clone(), material.to_material(directory)?);
+    }
+    let material = |reference: &MaterialReference| -> Result<Material, SceneError> {
+        match reference {
+            MaterialReference::Inline(description) => description.to_material(directory),
+            MaterialReference::Named(name) => match materials.get(name) {
+                Some(material) => Ok(material.clone()),
+                None => Err(SceneError::UnknownMaterial(name.clone())),
+            },
+        }
+    };
+    let six_materials = |references: &[MaterialReference; 6]| -> Result<Vec<Material>, SceneError> {
+        references.iter().map(&material).collect()
+    };
+
+    let mut entities = Vec::new();
+    let mut objects = Vec::new();
+    for entity in description.entities.iter() {
+        let first = entities.len();
+        match entity {
+            EntityDescription::Sphere {
+                center,
+                radius,
+                room,
+                material: reference,
+            } => {
+                let sphere = if *room {
+                    Sphere::new_room(point(*center), *radius)
+                } else {
+                    Sphere::new(point(*center), *radius)
+                };
+                entities.push((Entity::Sphere(sphere), material(reference)?));
+            }
+            EntityDescription::Triangle {
+                points: [p1, p2, p3],
+                room,
+                uvs,
+                material: reference,
+            } => {
+                let mut triangle = if *room {
+                    Triangle::new_room(point(*p1), point(*p2), point(*p3))
+                } else {
+                    Triangle::new(point(*p1), point(*p2), point(*p3))
+                };
+                if let Some([[u1, v1], [u2, v2], [u3, v3]]) = *uvs {
+                    // rooms swap the second and the third point
+                    triangle = if *room {
+                        triangle.with_uvs([(u1, v1), (u3, v3), (u2, v2)])
+                    } else {
+                        triangle.with_uvs([(u1, v1), (u2, v2), (u3, v3)])
+                    };
+                }
+                entities.push((Entity::Triangle(triangle), material(reference)?));
+            }
+            EntityDescription::Plane {
+                points: [p1, p2, p3],
+                material: reference,
+            } => entities.push((
+                Entity::Plane(Plane::new(point(*p1), point(*p2), point(*p3))),
+                material(reference)?,
+            )),
+            EntityDescription::Cube {
+                origin,
+                size,
+                material: reference,
+            } => entities.extend(shapes::cube(
+                point(*origin),
+                point(*size),
+                material(reference)?,
+            )),
+            EntityDescription::AbsoluteCube {
+                from,
+                to,
+                material: reference,
+            } => entities.extend(shapes::absolute_cube(
+                point(*from),
+                point(*to),
+                material(reference)?,
+            )),
+            EntityDescription::Room {
+                from,
+                to,
+                materials: references,
+            } => entities.extend(shapes::room(
+                point(*from),
+                point(*to),
+                &six_materials(references)?,
+            )),
+            EntityDescription::GeneralCube {
+                from,
+                to,
+                materials: references,
+                reverse_normals,
+            } => entities.extend(shapes::general_cube(
+                point(*from),
+                point(*to),
+                &six_materials(references)?,
+                *reverse_normals,
+            )),
+            EntityDescription::Mesh {
+                positions,
+                faces,
+                normals,
+                uvs,
+                material: reference,
+                materials: references,
+                face_materials,
+            } => {
+                let mut buffers = MeshBuffers::new(
+                    positions.iter().map(|position| point(*position)).collect(),
+                    faces.clone(),
+                );
+                buffers.normals = normals
+                    .as_ref()
+                    .map(|normals| normals.iter().map(|normal| point(*normal)).collect());
+                buffers.uvs = uvs
+                    .as_ref()
+                    .map(|uvs| uvs.iter().map(|&[u, v]| (u, v)).collect());
+                buffers.materials = references.iter().map(&material).collect::<Result<_, _>>()?;
+                buffers.face_materials = face_materials.clone();
+                let mesh = Mesh::new(buffers).map_err(SceneError::InvalidMesh)?;
+                entities.push((Entity::Mesh(mesh), material(reference)?));
+            }
+            EntityDescription::Obj {
+                path,
+                material: reference,
+                scale,
+                translate,
+            } => {
+                let mut options = ObjOptions::new(material(reference)?);
+                options.scale = *scale;
+                options.translation = point(*translate);
+                entities.extend(obj::load(directory.join(path), &options)?);
+            }
+        }
+        // the entities of one description are one object, except for the groups of obj files
+        let grouped = !matches!(entity, EntityDescription::Obj { .. });
+        objects.extend((first..entities.len()).map(|i| if grouped { first } else { i }));
+    }
+
+    let mut world = World::new(
+        entities,
+        description
+            .lights
+            .iter()
+            .map(LightDescription::to_light)
+            .collect::<Result<_, _>>()?,
+    )
+    .with_objects(objects);
+    world.ambient = description.ambient.to_ambient();
+    if let Some(camera) = &description.camera {
+        world.camera = camera.to_camera()?;
+    }
+
+    Ok(Scene {
+        world,
+        render: description.render,
+    })
+}
+
+fn point([x, y, z]: [Float; 3]) -> Point {
+    Point::new(x, y, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(text: &str) -> Result<Scene, SceneError> {
+        parse(text, Path::new(""))
+    }
+
+    fn light(color: &str) -> String {
+        format!(
+            "[[entities]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 5.0]\nradius = 1.0\n\
+             material = {{ type = \"light\", color = {}, light = 1.0 }}\n",
+            color
+        )
+    }
+
+    #[test]
+    fn integer_colors_of_ones_are_ambiguous() {
+        assert!(parse_str(&light("[255, 100, 0]")).is_ok());
+        assert!(parse_str(&light("[1.0, 1.0, 1.0]")).is_ok());
+        assert!(parse_str(&light("[1, 0.5, 0]")).is_ok());
+        assert!(parse_str(&light("[0, 0, 0]")).is_ok());
+        let error = parse_str(&light("[1, 1, 1]")).err().unwrap().to_string();
+        assert!(error.contains("could be bytes or floats"), "{}", error);
+        assert!(parse_str(&light("[300, 0, 0]")).is_err());
+    }
+
+    #[test]
+    fn cameras_looking_along_up_are_rejected() {
+        let camera = "[camera]\neye = [0.0, 0.0, 0.0]\ntarget = [0.0, 5.0, 0.0]\n";
+        let error = parse_str(camera).err().unwrap().to_string();
+        assert!(error.contains("up is parallel"), "{}", error);
+    }
+
+    #[test]
+    fn spot_angles_are_checked() {
+        let spot = |inner: Float, outer: Float| {
+            let text = format!(
+                "[[lights]]\ntype = \"spot\"\nposition = [0.0, 0.0, 0.0]\n\
+                 direction = [0.0, 0.0, 1.0]\ninner_angle = {:?}\nouter_angle = {:?}\n\
+                 intensity = 50.0\n",
+                inner, outer
+            );
+            parse_str(&text).map(|scene| scene.world.light[0])
+        };
+        match spot(20.0, 30.0).unwrap() {
+            // the default falloff does not follow the intensity
+            Light::Spot { falloff, .. } => assert_eq!(falloff, 1.0),
+            _ => panic!("not a spot light"),
+        }
+        assert!(spot(30.0, 30.0).is_ok());
+        assert!(spot(30.0, 180.0).is_ok());
+        for &(inner, outer) in [(30.0, 20.0), (0.0, 20.0), (-10.0, 20.0), (30.0, 190.0)].iter() {
+            let error = spot(inner, outer).err().unwrap().to_string();
+            assert!(error.starts_with("invalid light"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn texture_tables_reject_unknown_keys() {
+        let diffuse = |color: &str| {
+            let text = format!(
+                "[[entities]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 5.0]\nradius = 1.0\n\
+                 material = {{ type = \"diffuse\", color = {} }}\n",
+                color
+            );
+            parse_str(&text).err().map(|error| error.to_string())
+        };
+        assert_eq!(diffuse("0.5"), None);
+        assert_eq!(diffuse("\"gold\""), None);
+        assert_eq!(
+            diffuse("{ type = \"pattern\", pattern = \"perlin\", octaves = 2, scale = 3.0 }"),
+            None
+        );
+        let errors = [
+            (
+                "{ type = \"pattern\", pattern = \"perlin\", octave = 2 }",
+                "unknown field `octave`",
+            ),
+            (
+                "{ type = \"pattern\", pattern = \"checker\", first = { type = \"pattern\" } }",
+                "missing field `pattern`",
+            ),
+            (
+                "{ type = \"image\", path = \"wood.png\", repeat = true }",
+                "unknown field `repeat`",
+            ),
+            ("{ pattern = \"checker\" }", "need a type"),
+        ];
+        for (texture, message) in errors.iter() {
+            let error = diffuse(texture).unwrap();
+            assert!(error.contains(message), "{}", error);
+        }
+        // errors inside of bump maps are kept too
+        let bump = "[[entities]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, 5.0]\nradius = 1.0\n\
+                    material = { type = \"diffuse\", color = \"white\", bump = { height = \
+                    { type = \"pattern\", pattern = \"perlin\", octave = 2 } } }\n";
+        let error = parse_str(bump).err().unwrap().to_string();
+        assert!(error.contains("unknown field `octave`"), "{}", error);
+    }
+}
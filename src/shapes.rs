@@ -0,0 +1,89 @@
+use crate::{
+    entities::{Entity, Triangle},
+    geometry::Point,
+    material::Material,
+};
+
+pub fn cube(origin: Point, size: Point, material: Material) -> Vec<(Entity, Material)> {
+    absolute_cube(origin, origin + size, material)
+}
+
+pub fn absolute_cube(p000: Point, p111: Point, material: Material) -> Vec<(Entity, Material)> {
+    general_cube(p000, p111, &vec![material; 6], false)
+}
+
+pub fn room(p000: Point, p111: Point, materials: &[Material]) -> Vec<(Entity, Material)> {
+    general_cube(p000, p111, materials, true)
+}
+
+pub fn general_cube(
+    p000: Point,
+    p111: Point,
+    materials: &[Material],
+    reverse_normals: bool,
+) -> Vec<(Entity, Material)> {
+    let p001 = Point::new(p000.x, p000.y, p111.z);
+    let p010 = Point::new(p000.x, p111.y, p000.z);
+    let p100 = Point::new(p111.x, p000.y, p000.z);
+    let p011 = Point::new(p000.x, p111.y, p111.z);
+    let p101 = Point::new(p111.x, p000.y, p111.z);
+    let p110 = Point::new(p111.x, p111.y, p000.z);
+    vec![
+        (
+            triangle(p000, p100, p010, reverse_normals),
+            materials[0].clone(),
+        ),
+        (
+            triangle(p100, p110, p010, reverse_normals),
+            materials[0].clone(),
+        ),
+        (
+            triangle(p010, p110, p011, reverse_normals),
+            materials[1].clone(),
+        ),
+        (
+            triangle(p110, p111, p011, reverse_normals),
+            materials[1].clone(),
+        ),
+        (
+            triangle(p110, p100, p111, reverse_normals),
+            materials[2].clone(),
+        ),
+        (
+            triangle(p100, p101, p111, reverse_normals),
+            materials[2].clone(),
+        ),
+        (
+            triangle(p001, p101, p000, reverse_normals),
+            materials[3].clone(),
+        ),
+        (
+            triangle(p101, p100, p000, reverse_normals),
+            materials[3].clone(),
+        ),
+        (
+            triangle(p000, p001, p010, !reverse_normals),
+            materials[4].clone(),
+        ),
+        (
+            triangle(p001, p011, p010, !reverse_normals),
+            materials[4].clone(),
+        ),
+        (
+            triangle(p011, p111, p001, reverse_normals),
+            materials[5].clone(),
+        ),
+        (
+            triangle(p111, p101, p001, reverse_normals),
+            materials[5].clone(),
+        ),
+    ]
+}
+
+pub fn triangle(p0: Point, p1: Point, p2: Point, reversed_normal: bool) -> Entity {
+    if reversed_normal {
+        Entity::Triangle(Triangle::new_room(p0, p1, p2))
+    } else {
+        Entity::Triangle(Triangle::new(p0, p1, p2))
+    }
+}
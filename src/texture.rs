@@ -0,0 +1,274 @@
+use std::{ops::Mul, path::Path, sync::Arc};
+
+use crate::{
+    drawing::{srgb_eotf, Color, ColorMatrix},
+    film::{self, FilmError, HdrFormat},
+    geometry::Point,
+    Float,
+};
+
+// where a texture is looked up: the point in space for solid textures and the texture
+// coordinates of the entity for images
+#[derive(Clone, Copy, Debug)]
+pub struct TexturePoint {
+    pub point: Point,
+    pub uv: (Float, Float),
+}
+
+impl TexturePoint {
+    pub fn new(point: Point, uv: (Float, Float)) -> Self {
+        Self { point, uv }
+    }
+}
+
+// a texture computed from the point instead of looked up
+pub trait Procedural: Send + Sync {
+    fn value(&self, at: &TexturePoint) -> Color;
+}
+
+impl<F: Fn(&TexturePoint) -> Color + Send + Sync> Procedural for F {
+    fn value(&self, at: &TexturePoint) -> Color {
+        self(at)
+    }
+}
+
+// a color, or a scalar in all three channels, that varies over the surface
+#[derive(Clone)]
+pub enum Texture {
+    Constant(Color),
+    Image(Arc<ImageTexture>),
+    Procedural(Arc<dyn Procedural>),
+    // like a color scaled by a coefficient
+    Product(Arc<Texture>, Arc<Texture>),
+}
+
+impl Texture {
+    pub fn value(&self, at: &TexturePoint) -> Color {
+        match self {
+            Texture::Constant(color) => *color,
+            Texture::Image(image) => image.value(at.uv),
+            Texture::Procedural(procedural) => procedural.value(at),
+            Texture::Product(a, b) => a.value(at) * b.value(at),
+        }
+    }
+
+    // scalar textures are the mean of the channels, so a grayscale image works as one
+    pub fn scalar(&self, at: &TexturePoint) -> Float {
+        match self {
+            Texture::Constant(color) => mean(*color),
+            _ => mean(self.value(at)),
+        }
+    }
+
+    pub fn constant(&self) -> Option<Color> {
+        match self {
+            Texture::Constant(color) => Some(*color),
+            _ => None,
+        }
+    }
+
+    pub fn constant_scalar(&self) -> Option<Float> {
+        self.constant().map(mean)
+    }
+}
+
+fn mean(color: Color) -> Float {
+    let [r, g, b] = color.channels();
+    (r + g + b) / 3.0
+}
+
+impl From<Color> for Texture {
+    fn from(color: Color) -> Self {
+        Texture::Constant(color)
+    }
+}
+
+impl From<Float> for Texture {
+    fn from(value: Float) -> Self {
+        Texture::Constant(Color::new(value, value, value))
+    }
+}
+
+impl Mul<Texture> for Texture {
+    type Output = Texture;
+
+    fn mul(self, other: Texture) -> Texture {
+        match (self, other) {
+            (Texture::Constant(a), Texture::Constant(b)) => Texture::Constant(a * b),
+            (Texture::Constant(a), _) | (_, Texture::Constant(a)) if a == Color::BLACK => {
+                Texture::Constant(Color::BLACK)
+            }
+            (Texture::Constant(a), other) | (other, Texture::Constant(a)) if a == Color::WHITE => {
+                other
+            }
+            (a, b) => Texture::Product(Arc::new(a), Arc::new(b)),
+        }
+    }
+}
+
+impl Mul<Float> for Texture {
+    type Output = Texture;
+
+    fn mul(self, scale: Float) -> Texture {
+        self * Texture::from(scale)
+    }
+}
+
+// what happens to texture coordinates outside of [0, 1]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Wrap {
+    Repeat,
+    Clamp,
+    // repeats with every other copy flipped, so there are no seams
+    Mirror,
+}
+
+impl Wrap {
+    fn index(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+// an image over the texture coordinates, u goes right and v goes up, (0, 0) is the bottom left
+// corner of the image
+pub struct ImageTexture {
+    image: ColorMatrix,
+    wrap: Wrap,
+    // copies of the image per unit of texture coordinates
+    scale: (Float, Float),
+}
+
+impl ImageTexture {
+    pub fn new(image: ColorMatrix, wrap: Wrap) -> Self {
+        Self {
+            image,
+            wrap,
+            scale: (1.0, 1.0),
+        }
+    }
+
+    pub fn with_scale(mut self, u: Float, v: Float) -> Self {
+        self.scale = (u, v);
+        self
+    }
+
+    // exr, pfm and hdr files are linear, 8 bit images are srgb unless they are marked linear,
+    // like height or roughness maps
+    pub fn open<P: AsRef<Path>>(path: P, wrap: Wrap, linear: bool) -> Result<Self, FilmError> {
+        let path = path.as_ref();
+        if HdrFormat::from_path(path).is_some() {
+            return Ok(ImageTexture::new(film::load(path)?, wrap));
+        }
+        let image = image::open(path)?.to_rgb8();
+        let decode = |x: u8| {
+            let x = x as Float / 255.0;
+            if linear {
+                x
+            } else {
+                srgb_eotf(x)
+            }
+        };
+        let mut matrix = ColorMatrix::new(image.width() as usize, image.height() as usize);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let [r, g, b] = pixel.0;
+            matrix.set(
+                x as usize,
+                y as usize,
+                Color::new(decode(r), decode(g), decode(b)),
+            );
+        }
+        Ok(ImageTexture::new(matrix, wrap))
+    }
+
+    // bilinear interpolation between the centers of the four closest pixels
+    pub fn value(&self, (u, v): (Float, Float)) -> Color {
+        let (width, height) = (self.image.width(), self.image.height());
+        let x = u * self.scale.0 * width as Float - 0.5;
+        let y = (1.0 - v * self.scale.1) * height as Float - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        let texel = |x: i64, y: i64| {
+            self.image
+                .get(self.wrap.index(x, width), self.wrap.index(y, height))
+        };
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1, y0) * fx;
+        let bottom = texel(x0, y0 + 1) * (1.0 - fx) + texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // black and white columns, the right one white
+    fn stripes(wrap: Wrap) -> ImageTexture {
+        let mut image = ColorMatrix::new(2, 2);
+        image.set(1, 0, Color::WHITE);
+        image.set(1, 1, Color::WHITE);
+        ImageTexture::new(image, wrap)
+    }
+
+    fn red(texture: &ImageTexture, u: Float) -> Float {
+        texture.value((u, 0.5)).channels()[0]
+    }
+
+    #[test]
+    fn bilinear_between_pixel_centers() {
+        let texture = stripes(Wrap::Clamp);
+        assert_eq!(red(&texture, 0.25), 0.0);
+        assert_eq!(red(&texture, 0.75), 1.0);
+        assert!((red(&texture, 0.5) - 0.5).abs() < 1e-12);
+        // clamped, the edges keep the color of the outermost pixels
+        assert_eq!(red(&texture, 0.0), 0.0);
+        assert_eq!(red(&texture, 1.2), 1.0);
+    }
+
+    #[test]
+    fn wrap_modes() {
+        // half a pixel beyond the right edge is between the white column and the one next to it
+        let repeat = stripes(Wrap::Repeat);
+        assert!((red(&repeat, 1.0) - 0.5).abs() < 1e-12);
+        assert_eq!(red(&repeat, 1.25), 0.0);
+        assert_eq!(red(&repeat, -0.25), 1.0);
+        let mirror = stripes(Wrap::Mirror);
+        assert_eq!(red(&mirror, 1.0), 1.0);
+        assert_eq!(red(&mirror, 1.25), 1.0);
+        assert_eq!(red(&mirror, 1.75), 0.0);
+        assert_eq!(red(&mirror, -0.25), 0.0);
+    }
+
+    #[test]
+    fn products_fold_constants() {
+        let at = TexturePoint::new(Point::new(0.0, 0.0, 0.0), (0.75, 0.5));
+        let scaled = Texture::from(Color::new(0.5, 1.0, 0.25)) * 2.0;
+        assert_eq!(scaled.constant(), Some(Color::new(1.0, 2.0, 0.5)));
+        let image = Texture::Image(Arc::new(stripes(Wrap::Clamp)));
+        let tinted = image * Texture::from(Color::new(0.5, 0.5, 0.5));
+        assert!(tinted.constant().is_none());
+        assert_eq!(tinted.value(&at), Color::new(0.5, 0.5, 0.5));
+        assert!((tinted.scalar(&at) - 0.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn procedural_textures_see_the_point() {
+        let height = |at: &TexturePoint| Color::new(at.point.y, at.point.y, at.point.y);
+        let texture = Texture::Procedural(Arc::new(height));
+        let at = TexturePoint::new(Point::new(1.0, 0.25, 3.0), (0.0, 0.0));
+        assert_eq!(texture.scalar(&at), 0.25);
+    }
+}
@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const TILE_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    // the tile grown by margin pixels on every side, clipped to the image
+    pub fn expand(&self, margin: usize, image_width: usize, image_height: usize) -> Tile {
+        let x = self.x.saturating_sub(margin);
+        let y = self.y.saturating_sub(margin);
+        Tile {
+            x,
+            y,
+            width: (self.x + self.width + margin).min(image_width) - x,
+            height: (self.y + self.height + margin).min(image_height) - y,
+        }
+    }
+}
+
+// shared by all worker threads, every thread takes the next tile as soon as it is done with
+// its last one, so threads that got cheap tiles simply do more of them
+pub struct TileQueue {
+    tiles: Vec<Tile>,
+    next: AtomicUsize,
+}
+
+impl TileQueue {
+    pub fn new(width: usize, height: usize, size: usize) -> Self {
+        let mut tiles = Vec::new();
+        for y in (0..height).step_by(size) {
+            for x in (0..width).step_by(size) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+        Self {
+            tiles,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    // tiles are handed out in order, once all of them are taken the queue starts over with
+    // the next round, the round is returned together with the tile
+    pub fn next(&self) -> (usize, Tile) {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        (
+            index / self.tiles.len(),
+            self.tiles[index % self.tiles.len()],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiles_cover_the_image_once_per_round() {
+        let queue = TileQueue::new(37, 20, 16);
+        assert_eq!(queue.count(), 6);
+        let mut covered = vec![0; 37 * 20];
+        for _ in 0..queue.count() {
+            let (round, tile) = queue.next();
+            assert_eq!(round, 0);
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[y * 37 + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&count| count == 1));
+        assert_eq!(
+            queue.next(),
+            (
+                1,
+                Tile {
+                    x: 0,
+                    y: 0,
+                    width: 16,
+                    height: 16
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn expanded_tiles_stay_inside_the_image() {
+        let tile = Tile {
+            x: 32,
+            y: 0,
+            width: 5,
+            height: 16,
+        };
+        assert_eq!(
+            tile.expand(2, 37, 20),
+            Tile {
+                x: 30,
+                y: 0,
+                width: 7,
+                height: 18
+            }
+        );
+    }
+}
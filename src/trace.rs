@@ -1,264 +1,835 @@
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use rand::prelude::*;
+use rand::Rng;
 
 use crate::{
+    bsdf::{Bsdf, Hit},
     drawing::{Color, ColorMatrix},
+    entities::IntersectionResult,
+    filter::{Filter, FilteredImage},
     geometry::Point,
-    world::World,
+    light::LightSample,
+    material::Material,
+    medium::MediumStack,
+    texture::TexturePoint,
+    tiles::{Tile, TileQueue, TILE_SIZE},
+    world::{CastResult, World},
     Float,
 };
 
-const FOV: Float = std::f64::consts::PI / 1.5;
-
-const MAX_DEPTH: usize = 10;
-
-fn thread_number() -> usize {
-    num_cpus::get()
+#[derive(Clone, Copy, Debug)]
+pub struct TraceSettings {
+    pub threads: usize,
+    // samples per pixel of the path tracer, unbounded if None
+    pub samples: Option<usize>,
+    // wall-clock budget of the path tracer, unbounded if None
+    pub time_limit: Option<Duration>,
+    pub max_depth: usize,
+    // the ray tracer shoots supersampling² jittered rays per pixel, one through the center if 1
+    pub supersampling: usize,
+    // reconstruction filter of the ray tracer
+    pub filter: Filter,
+    // shadow rays of the ray tracer per pixel for every area light and for all emissive entities
+    // together, the supersampled rays of a pixel share them
+    pub light_samples: usize,
 }
 
-#[allow(dead_code)]
-pub fn trace(world: &World, width: usize, height: usize) -> ColorMatrix {
-    trace_in_vertical_bounds(world, width, height, 0, height)
+impl Default for TraceSettings {
+    fn default() -> Self {
+        Self {
+            threads: num_cpus::get(),
+            samples: None,
+            time_limit: None,
+            max_depth: 10,
+            supersampling: 1,
+            filter: Filter::Box,
+            light_samples: 16,
+        }
+    }
 }
 
 #[allow(dead_code)]
-pub fn trace_parallel(world: &World, width: usize, height: usize) -> ColorMatrix {
-    let thread_number = thread_number();
-    let mut matrix = ColorMatrix::new(width, height);
-    let (sender, receiver): (Sender<ColorMatrix>, Receiver<ColorMatrix>) = mpsc::channel();
-    let batch_size = height / thread_number;
-    let start = std::time::Instant::now();
-    for i in 0..thread_number {
-        let sender = sender.clone();
-        let world = world.clone();
-        let from = batch_size * i;
-        let to = if i == thread_number - 1 {
-            height
-        } else {
-            batch_size * i + batch_size
-        };
-        println!("{}", to - from);
-        thread::spawn(move || {
-            sender
-                .send(trace_in_vertical_bounds(&world, width, height, from, to))
-                .expect("Could not send result");
-        });
-    }
-    println!(
-        "All threads created for {} secs",
-        (std::time::Instant::now() - start).as_secs_f64()
-    );
-
-    for _ in 0..thread_number {
-        matrix += receiver.recv().expect("Could not receive result");
-    }
-    println!(
-        "All messages received for {} secs",
-        (std::time::Instant::now() - start).as_secs_f64()
-    );
-    matrix
+pub fn trace(world: &World, width: usize, height: usize, settings: TraceSettings) -> ColorMatrix {
+    trace_parallel(
+        world,
+        width,
+        height,
+        TraceSettings {
+            threads: 1,
+            ..settings
+        },
+    )
 }
 
-fn trace_in_vertical_bounds(
+pub fn trace_parallel(
     world: &World,
     width: usize,
     height: usize,
-    from: usize,
-    to: usize,
+    settings: TraceSettings,
 ) -> ColorMatrix {
-    let mut matrix = ColorMatrix::new(width, height);
-    for j in from..to {
-        for i in 0..width {
-            matrix.set(
-                i,
-                j,
-                trace_ray(
-                    world,
-                    Point::new(0.0, 0.0, 0.0),
-                    vec_for_angle(
-                        (i as Float - width as Float / 2.0) / width as Float * FOV,
-                        (j as Float - height as Float / 2.0) / height as Float * FOV,
-                    ),
-                    0,
-                ),
-            );
+    let queue = TileQueue::new(width, height, TILE_SIZE);
+    let image = Mutex::new(FilteredImage::new(width, height, settings.filter));
+    thread::scope(|scope| {
+        for _ in 0..settings.threads.max(1) {
+            scope.spawn(|| loop {
+                let (round, tile) = queue.next();
+                if round > 0 {
+                    break;
+                }
+                let traced = trace_tile(world, width, height, tile, settings);
+                image
+                    .lock()
+                    .expect("tracing thread panicked")
+                    .merge(&traced);
+            });
         }
-    }
-    matrix
+    });
+    let image = image.into_inner().expect("tracing thread panicked");
+    image.to_matrix()
 }
 
-fn vec_for_angle(x: Float, y: Float) -> Point {
-    Point::new(x.sin(), y, x.cos())
+// samples near the border of the tile also land in the pixels around it
+fn filter_margin(filter: Filter) -> usize {
+    filter.radius().ceil() as usize
 }
 
-const KA: Float = 1.0;
-
-const IA: Float = 0.1;
-
-const KS: Float = 0.3;
-
-const KD: Float = 1.0;
+fn trace_tile(
+    world: &World,
+    width: usize,
+    height: usize,
+    tile: Tile,
+    settings: TraceSettings,
+) -> FilteredImage {
+    let region = tile.expand(filter_margin(settings.filter), width, height);
+    let mut image = FilteredImage::region(
+        region.x,
+        region.y,
+        region.width,
+        region.height,
+        settings.filter,
+    );
+    let mut rng = rand::thread_rng();
+    let strata = settings.supersampling.max(1);
+    let light_samples = (settings.light_samples / (strata * strata)).max(1);
+    for j in tile.y..tile.y + tile.height {
+        for i in tile.x..tile.x + tile.width {
+            // one jittered sample in every cell of a strata x strata grid over the pixel
+            for sy in 0..strata {
+                for sx in 0..strata {
+                    let (jitter_x, jitter_y) = if strata == 1 {
+                        (0.5, 0.5)
+                    } else {
+                        (rng.gen::<Float>(), rng.gen::<Float>())
+                    };
+                    let x = i as Float + (sx as Float + jitter_x) / strata as Float;
+                    let y = j as Float + (sy as Float + jitter_y) / strata as Float;
+                    let color = trace_ray(
+                        world,
+                        world.camera.eye(),
+                        world.camera.ray(x, y, width, height),
+                        0,
+                        settings.max_depth,
+                        light_samples,
+                        &MediumStack::new(),
+                    );
+                    image.add_sample(x, y, color);
+                }
+            }
+        }
+    }
+    image
+}
 
-const SHINESS: Float = 80.0;
+// the points the camera sees get light_samples shadow rays per area light, they make the soft
+// shadows, reflections and refractions only get a few
+const SECONDARY_AREA_LIGHT_SAMPLES: usize = 4;
 
-fn trace_ray(world: &World, origin: Point, direction: Point, depth: usize) -> Color {
+// media are the transparent materials the ray is inside of
+fn trace_ray(
+    world: &World,
+    origin: Point,
+    direction: Point,
+    depth: usize,
+    max_depth: usize,
+    light_samples: usize,
+    media: &MediumStack,
+) -> Color {
     let entity = match world.cast_ray(origin, direction) {
         None => return Color::new(0.0, 0.0, 0.0),
         Some(real_cast_result) => real_cast_result,
     };
+    // light coming back along the ray is absorbed by the medium it travels through
+    let transmittance = media.transmittance(entity.intersection.distance);
+    shade_ray(
+        world,
+        entity,
+        direction,
+        depth,
+        max_depth,
+        light_samples,
+        media,
+    ) * transmittance
+}
 
+fn shade_ray(
+    world: &World,
+    entity: CastResult,
+    direction: Point,
+    depth: usize,
+    max_depth: usize,
+    light_samples: usize,
+    media: &MediumStack,
+) -> Color {
+    let object = entity.object;
     let material = entity.material;
     let entity = entity.intersection;
-    let mut color = material.color;
-
-    let mut shade = 0.0;
-    for &light in world.light.iter() {
-        let shadowed =
-            match world.cast_ray(entity.intersection_point, light - entity.intersection_point) {
-                None => false,
-                Some(shadow_entity) => {
-                    shadow_entity.intersection.distance < (light - entity.intersection_point).len()
-                }
-            };
-        if shadowed {
-            shade += KA * IA;
-        } else {
-            let light_vector = (entity.intersection_point - light).normalize();
-            shade += KA * IA
-                + KD * (entity.normal * light_vector)
-                + KS * (entity.normal * light_vector.reflect(entity.normal)).powf(SHINESS);
+
+    let entering = Material::is_entered_by(direction, entity.normal);
+    let outside = match material.medium {
+        None => 1.0,
+        Some(medium) => match media.outside_of(object, &medium, entering) {
+            Some(outside) => outside,
+            None => {
+                return trace_ray(
+                    world,
+                    entity.intersection_point,
+                    direction,
+                    depth,
+                    max_depth,
+                    light_samples,
+                    &crossed(media, object, material, entering),
+                );
+            }
+        },
+    };
+
+    // emissive entities show their own radiance
+    if material.light > 0.00001 {
+        return material.color * material.light;
+    }
+
+    // the bsdf and the phong highlights summed over all lights and emissive entities, highlights
+    // have the color of the light
+    let hit = Hit::new(
+        direction,
+        shading_normal(material, &entity, direction),
+        outside,
+        material.refraction_coefficient(),
+    )
+    .at(texture_point(&entity));
+    let point = entity.intersection_point;
+    let view = (direction * -1.0).normalize();
+    let mut rng = rand::thread_rng();
+    let mut lit = Color::BLACK;
+    let area_samples = if depth == 0 {
+        light_samples
+    } else {
+        light_samples.min(SECONDARY_AREA_LIGHT_SAMPLES)
+    };
+    for light in world.light.iter() {
+        let samples = if light.is_delta() { 1 } else { area_samples };
+        for _ in 0..samples {
+            if let Some(sample) = light.sample(point, &mut rng) {
+                lit = lit
+                    + shade_light(world, material, &hit, point, view, &sample)
+                        * (1.0 / samples as Float);
+            }
         }
     }
-    color *= shade / world.light.len() as Float;
+    for _ in 0..area_samples {
+        if let Some((sample, _)) = sample_emitter_light(world, point, &mut rng) {
+            lit = lit
+                + shade_light(world, material, &hit, point, view, &sample)
+                    * (1.0 / area_samples as Float);
+        }
+    }
+    let ambient = world.ambient.color * (material.phong.ka * world.ambient.intensity);
+    let mut color = material.bsdf.albedo(&hit) * ambient + lit;
 
-    if depth >= MAX_DEPTH {
+    if depth >= max_depth {
         return color;
     }
 
-    if material.reflection > 0.0 && material.diffuse.abs() < 0.000001 {
-        let mirror = trace_ray(
-            world,
-            entity.intersection_point,
-            direction.reflect(entity.normal),
-            depth + 1,
-        );
-        color = color * (1.0 - material.reflection) + mirror * material.reflection;
+    // every specular direction of the bsdf is followed, refracted rays cross into the medium or
+    // out of it, rough surfaces are seen as smooth ones
+    for lobe in material.bsdf.specular(&hit) {
+        let next_media = if lobe.refracted {
+            crossed(media, object, material, entering)
+        } else {
+            media.clone()
+        };
+        color = color
+            + trace_ray(
+                world,
+                point,
+                lobe.direction,
+                depth + 1,
+                max_depth,
+                light_samples,
+                &next_media,
+            ) * lobe.weight;
     }
 
-    if material.transparency > 0.0 {
-        let visible_trough = trace_ray(
-            world,
-            entity.intersection_point,
-            direction.refract(entity.normal, material.refraction_coefficient),
-            depth + 1,
-        );
-        color = color * (1.0 - material.transparency) + visible_trough * material.transparency;
+    color
+}
+
+// entities without texture coordinates show the texture at their origin
+fn texture_point(entity: &IntersectionResult) -> TexturePoint {
+    TexturePoint::new(entity.intersection_point, entity.uv.unwrap_or((0.0, 0.0)))
+}
+
+// the normal the bsdf sees, bent by the bump map of the material
+fn shading_normal(material: &Material, entity: &IntersectionResult, direction: Point) -> Point {
+    match &material.bump {
+        None => entity.normal,
+        Some(bump) => {
+            let bent = bump.normal(entity.normal, entity.tangents, &texture_point(entity));
+            // bent away from the ray, the bsdf would see it on the other side of the surface
+            if (bent * direction) * (entity.normal * direction) <= 0.0 {
+                entity.normal
+            } else {
+                bent
+            }
+        }
     }
+}
 
-    return color;
+// media on the other side of the surface of object, which has material
+fn crossed(media: &MediumStack, object: usize, material: &Material, entering: bool) -> MediumStack {
+    let mut media = media.clone();
+    if let Some(medium) = material.medium {
+        media.cross(object, &medium, entering);
+    }
+    media
 }
 
-#[allow(dead_code)]
-pub fn path_trace(world: &World, fname: String, width: usize, height: usize) {
-    let mut matrix = ColorMatrix::new(width, height);
-    let (sender, receiver): (Sender<ColorMatrix>, Receiver<ColorMatrix>) = mpsc::channel();
-    for _ in 0..thread_number() {
-        let sender = sender.clone();
-        let world = world.clone();
-        thread::spawn(move || loop {
-            let mut matrix = ColorMatrix::new(width, height);
-            for iteration in 0..8 {
-                for j in 0..height {
-                    for i in 0..width {
-                        let traced = trace_path(
-                            &world,
-                            Point::new(0.0, 0.0, 0.0),
-                            vec_for_angle(
-                                (i as Float - width as Float / 2.0) / width as Float * FOV,
-                                (j as Float - height as Float / 2.0) / height as Float * FOV,
-                            ),
-                            0,
-                        );
-                        matrix.set(
-                            i,
-                            j,
-                            (matrix.get(i, j) * iteration as Float + traced)
-                                * (1.0 / (iteration + 1) as Float),
-                        );
+// light reflected towards view by one light sample, the phong highlight is divided by pi like
+// the lambertian bsdf so both renderers agree on the brightness of a scene
+fn shade_light(
+    world: &World,
+    material: &Material,
+    hit: &Hit,
+    point: Point,
+    view: Point,
+    sample: &LightSample,
+) -> Color {
+    let normal = hit.facing_normal();
+    if normal * sample.direction <= 0.0 || !is_visible(world, point, sample) {
+        return Color::BLACK;
+    }
+    let phong = &material.phong;
+    let highlight = phong.highlight(normal, sample.direction, view);
+    sample.irradiance
+        * (material.bsdf.eval(hit, sample.direction) * phong.kd
+            + Color::WHITE * (phong.ks * highlight / PI))
+}
+
+// an emissive entity seen as an area light, one uniformly sampled point of it together with
+// the solid angle density of sampling it
+fn sample_emitter_light<R: Rng>(
+    world: &World,
+    point: Point,
+    rng: &mut R,
+) -> Option<(LightSample, Float)> {
+    let sample = world.sample_emitter(rng)?;
+    let to_light = sample.point - point;
+    let distance = to_light.len();
+    let direction = to_light / distance;
+    let cos_light = (direction * sample.normal.normalize()).abs();
+    if distance < 0.000000001 || cos_light < 0.000000001 {
+        return None;
+    }
+    let pdf = sample.pdf * distance * distance / cos_light;
+    Some((
+        LightSample {
+            direction,
+            distance,
+            irradiance: sample.radiance * (1.0 / pdf),
+            entity: Some(sample.entity),
+        },
+        pdf,
+    ))
+}
+
+// nothing between point and the sampled point of the light, points of emissive entities are
+// only visible if the shadow ray hits that entity there
+fn is_visible(world: &World, point: Point, sample: &LightSample) -> bool {
+    let reached = |distance: Float| distance > sample.distance * 0.999 - 0.0001;
+    match (world.cast_ray(point, sample.direction), sample.entity) {
+        (None, entity) => entity.is_none(),
+        (Some(hit), None) => reached(hit.intersection.distance),
+        (Some(hit), Some(entity)) => {
+            hit.entity == entity
+                && reached(hit.intersection.distance)
+                && hit.intersection.distance < sample.distance * 1.001 + 0.0001
+        }
+    }
+}
+
+// samples per pixel a thread takes every time it claims a tile
+const PASSES_PER_ROUND: usize = 8;
+
+// renders until settings.samples or settings.time_limit is reached, runs forever if neither is set,
+// progress is called with the current image and its samples per pixel every time a round over
+// all tiles is complete
+pub fn path_trace_with_progress<F>(
+    world: &World,
+    width: usize,
+    height: usize,
+    settings: TraceSettings,
+    progress: F,
+) -> ColorMatrix
+where
+    F: FnMut(&ColorMatrix, usize),
+{
+    // jittered samples averaged inside of their pixel
+    let image = FilteredImage::new(width, height, Filter::Box);
+    path_trace_image(world, width, height, image, 0, settings, progress)
+}
+
+// continues a render of the world that has the given samples per pixel, settings.samples
+// counts them too
+pub fn resume_path_trace_with_progress<F>(
+    world: &World,
+    matrix: &ColorMatrix,
+    samples: usize,
+    settings: TraceSettings,
+    progress: F,
+) -> ColorMatrix
+where
+    F: FnMut(&ColorMatrix, usize),
+{
+    let image = FilteredImage::from_matrix(matrix, samples as Float, Filter::Box);
+    let settings = TraceSettings {
+        samples: settings.samples.map(|total| total.saturating_sub(samples)),
+        ..settings
+    };
+    let (width, height) = (matrix.width(), matrix.height());
+    path_trace_image(world, width, height, image, samples, settings, progress)
+}
+
+fn path_trace_image<F>(
+    world: &World,
+    width: usize,
+    height: usize,
+    image: FilteredImage,
+    initial_samples: usize,
+    settings: TraceSettings,
+    mut progress: F,
+) -> ColorMatrix
+where
+    F: FnMut(&ColorMatrix, usize),
+{
+    let start = Instant::now();
+    let queue = TileQueue::new(width, height, TILE_SIZE);
+    let image = Mutex::new(image);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver): (Sender<usize>, Receiver<usize>) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..settings.threads.max(1) {
+            let sender = sender.clone();
+            let (queue, image, stop) = (&queue, &image, &stop);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let (round, tile) = queue.next();
+                    let passes = passes_in_round(round, settings.samples);
+                    if passes == 0 {
+                        break;
+                    }
+                    let traced = path_trace_tile(world, width, height, tile, passes, settings);
+                    image
+                        .lock()
+                        .expect("path tracing thread panicked")
+                        .merge(&traced);
+                    if sender.send(round).is_err() {
+                        break;
                     }
                 }
+            });
+        }
+        // workers hold the remaining senders, so the channel disconnects once all of them are done
+        drop(sender);
+
+        let deadline = settings.time_limit.map(|limit| start + limit);
+        let mut finished_tiles: Vec<usize> = Vec::new();
+        let mut finished_rounds = 0;
+        let mut samples = initial_samples;
+        loop {
+            let received = match deadline {
+                None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break;
+                    }
+                    receiver.recv_timeout(deadline - now)
+                }
+            };
+            let round = match received {
+                Ok(round) => round,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            if finished_tiles.len() <= round {
+                finished_tiles.resize(round + 1, 0);
             }
-            sender.send(matrix).expect("could not send matrix");
-        });
+            finished_tiles[round] += 1;
+            // rounds can finish out of order, the image only gains samples once the earlier ones are done
+            let mut complete = false;
+            while finished_tiles.get(finished_rounds) == Some(&queue.count()) {
+                samples += passes_in_round(finished_rounds, settings.samples);
+                finished_rounds += 1;
+                complete = true;
+            }
+            if complete {
+                let matrix = image
+                    .lock()
+                    .expect("path tracing thread panicked")
+                    .to_matrix();
+                progress(&matrix, samples);
+            }
+        }
+        stop.store(true, Ordering::Relaxed);
+    });
+
+    // after a time limit some tiles may have one round more than others, every pixel is
+    // normalized by its own number of samples
+    let image = image.into_inner().expect("path tracing thread panicked");
+    image.to_matrix()
+}
+
+// the last round only takes the samples that are left
+fn passes_in_round(round: usize, samples: Option<usize>) -> usize {
+    match samples {
+        None => PASSES_PER_ROUND,
+        Some(samples) => samples
+            .saturating_sub(round * PASSES_PER_ROUND)
+            .min(PASSES_PER_ROUND),
     }
-    for iteration in 0.. {
-        let new_matrix = receiver.recv().expect("failed to receive");
-        matrix.add_iteration(new_matrix, iteration);
-        print!("iteration {} finished, ", iteration);
-        match matrix.to_image().save(fname.as_str()) {
-            Ok(_) => println!("image flushed"),
-            Err(_) => println!("problems flushing image"),
-        };
+}
+
+fn path_trace_tile(
+    world: &World,
+    width: usize,
+    height: usize,
+    tile: Tile,
+    passes: usize,
+    settings: TraceSettings,
+) -> FilteredImage {
+    let mut image = FilteredImage::region(tile.x, tile.y, tile.width, tile.height, Filter::Box);
+    let mut rng = rand::thread_rng();
+    for _ in 0..passes {
+        for j in tile.y..tile.y + tile.height {
+            for i in tile.x..tile.x + tile.width {
+                // a new point of the pixel every pass, averaging them is a box filter
+                let x = i as Float + rng.gen::<Float>();
+                let y = j as Float + rng.gen::<Float>();
+                let traced = trace_path(
+                    world,
+                    world.camera.eye(),
+                    world.camera.ray(x, y, width, height),
+                    0,
+                    settings.max_depth,
+                    None,
+                    &MediumStack::new(),
+                );
+                image.add_sample(x, y, traced);
+            }
+        }
     }
+    image
 }
 
-fn trace_path(world: &World, origin: Point, direction: Point, depth: usize) -> Color {
-    let entity = match world.cast_ray(origin, direction) {
+// bsdf_pdf is the solid angle density of the bounce that produced this ray if the emitter it may hit
+// was also sampled directly, it is used to weight the two strategies with multiple importance sampling,
+// media are the transparent materials the path is inside of
+fn trace_path(
+    world: &World,
+    origin: Point,
+    direction: Point,
+    depth: usize,
+    max_depth: usize,
+    bsdf_pdf: Option<Float>,
+    media: &MediumStack,
+) -> Color {
+    let cast = match world.cast_ray(origin, direction) {
         None => return Color::new(0.0, 0.0, 0.0),
         Some(real_cast_result) => real_cast_result,
     };
+    // light coming back along the path is absorbed by the medium it travels through
+    let transmittance = media.transmittance(cast.intersection.distance);
+    shade_path(world, &cast, direction, depth, max_depth, bsdf_pdf, media) * transmittance
+}
 
-    let material = entity.material;
-    let entity = entity.intersection;
-    let mut color = material.color;
+fn shade_path(
+    world: &World,
+    cast: &CastResult,
+    direction: Point,
+    depth: usize,
+    max_depth: usize,
+    bsdf_pdf: Option<Float>,
+    media: &MediumStack,
+) -> Color {
+    let material = cast.material;
+    let entity = &cast.intersection;
+
+    let entering = Material::is_entered_by(direction, entity.normal);
+    let outside = match material.medium {
+        None => 1.0,
+        Some(medium) => match media.outside_of(cast.object, &medium, entering) {
+            Some(outside) => outside,
+            None => {
+                // shadow rays stop at the hidden surface, so an emitter behind it was not sampled
+                return trace_path(
+                    world,
+                    entity.intersection_point,
+                    direction,
+                    depth,
+                    max_depth,
+                    None,
+                    &crossed(media, cast.object, material, entering),
+                );
+            }
+        },
+    };
 
     if material.light > 0.00001 {
-        return color * material.light;
+        let weight = match bsdf_pdf {
+            None => 1.0,
+            Some(bsdf_pdf) => {
+                let direction = direction.normalize();
+                let cos_light = (direction * entity.normal.normalize()).abs();
+                let light_pdf =
+                    world.emitter_pdf(cast.entity, material) * entity.distance * entity.distance
+                        / cos_light;
+                power_heuristic(bsdf_pdf, light_pdf)
+            }
+        };
+        return material.color * material.light * weight;
     }
 
-    if depth == MAX_DEPTH {
+    if depth >= max_depth {
         return Color::BLACK;
     }
 
-    if material.transparency > 0.00001 {
-        let visible_trough = trace_path(
-            world,
-            entity.intersection_point,
-            direction.refract(entity.normal, material.refraction_coefficient),
-            depth + 1,
-        );
-        color = visible_trough * color * material.transparency;
-    }
+    // light sampling is combined with a bounce sampled from the bsdf
+    let point = entity.intersection_point;
+    let hit = Hit::new(
+        direction,
+        shading_normal(material, entity, direction),
+        outside,
+        material.refraction_coefficient(),
+    )
+    .at(texture_point(entity));
+    let bsdf = material.bsdf.as_ref();
+    let mut rng = rand::thread_rng();
+    let direct = sample_direct_light(world, point, &hit, bsdf, &mut rng);
+    let sample = match bsdf.sample(&hit, &mut rng) {
+        None => return direct,
+        Some(sample) => sample,
+    };
+    // light is only sampled on the side of the surface the path came from
+    let (bsdf_pdf, next_media) = if sample.refracted {
+        (None, crossed(media, cast.object, material, entering))
+    } else {
+        (sample.pdf, media.clone())
+    };
+    let bounce = trace_path(
+        world,
+        point,
+        sample.direction,
+        depth + 1,
+        max_depth,
+        bsdf_pdf,
+        &next_media,
+    );
+    bounce * sample.weight + direct
+}
 
-    if material.reflection > 0.00001 {
-        let mirror = trace_path(
-            world,
-            entity.intersection_point,
-            if material.diffuse > 0.00001 {
-                diffuse(entity.normal, material.diffuse)
-            } else {
-                direction.reflect(entity.normal)
-            },
-            depth + 1,
-        );
-        color = color * mirror * material.reflection;
+// light reaching the eye over the surface from one sampled point of an emissive entity, weighted
+// against the bsdf samples, and from one sample of every light, lights can not be hit by bounces
+// so there is nothing to weight them against
+fn sample_direct_light<R: Rng>(
+    world: &World,
+    point: Point,
+    hit: &Hit,
+    bsdf: &dyn Bsdf,
+    rng: &mut R,
+) -> Color {
+    let facing = hit.facing_normal();
+    let mut color = Color::BLACK;
+    if let Some((sample, light_pdf)) = sample_emitter_light(world, point, rng) {
+        let value = bsdf.eval(hit, sample.direction);
+        if sample.direction * facing > 0.0
+            && value != Color::BLACK
+            && is_visible(world, point, &sample)
+        {
+            let bsdf_pdf = bsdf.pdf(hit, sample.direction);
+            color = color + sample.irradiance * value * power_heuristic(light_pdf, bsdf_pdf);
+        }
     }
+    for light in world.light.iter() {
+        let sample = match light.sample(point, rng) {
+            None => continue,
+            Some(sample) => sample,
+        };
+        let value = bsdf.eval(hit, sample.direction);
+        if sample.direction * facing > 0.0
+            && value != Color::BLACK
+            && is_visible(world, point, &sample)
+        {
+            color = color + sample.irradiance * value;
+        }
+    }
+    color
+}
 
-    return color;
+fn power_heuristic(pdf: Float, other_pdf: Float) -> Float {
+    pdf * pdf / (pdf * pdf + other_pdf * other_pdf)
 }
 
-fn diffuse(vec: Point, diffusion: Float) -> Point {
-    let vec = vec.normalize();
-    let max_angle = 1.0 * std::f64::consts::PI * diffusion;
-    let mut rng = rand::thread_rng();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        entities::{Entity, Sphere, Triangle},
+        material::Material,
+    };
 
-    let angle = Point::new(
-        (rng.gen::<f64>() - 0.0) * max_angle,
-        (rng.gen::<f64>() - 0.0) * max_angle,
-        (rng.gen::<f64>() - 0.0) * max_angle,
-    );
+    // every camera ray hits the emissive walls, so every sample gives the same color
+    fn glowing_room() -> World {
+        World::new(
+            vec![(
+                Entity::Sphere(Sphere::new_room(Point::new(0.0, 0.0, 0.0), 10.0)),
+                Material::new_light(Color::new(0.202, 0.402, 0.602), 1.0),
+            )],
+            vec![],
+        )
+    }
+
+    fn path_trace(
+        world: &World,
+        width: usize,
+        height: usize,
+        settings: TraceSettings,
+    ) -> ColorMatrix {
+        path_trace_with_progress(world, width, height, settings, |_, _| {})
+    }
+
+    fn assert_glowing(color: Color) {
+        let expected = [0.202, 0.402, 0.602];
+        for (channel, expected) in color.channels().iter().zip(expected.iter()) {
+            assert!((channel - expected).abs() < 1e-9, "{:?}", color);
+        }
+    }
+
+    #[test]
+    fn ray_trace_shows_emitters() {
+        let matrix = trace(&glowing_room(), 8, 6, TraceSettings::default());
+        assert_glowing(matrix.get(3, 2));
+    }
+
+    #[test]
+    fn path_trace_stops_after_samples() {
+        let settings = TraceSettings {
+            threads: 3,
+            samples: Some(20),
+            ..TraceSettings::default()
+        };
+        let (mut calls, mut last_samples) = (0, 0);
+        let matrix = path_trace_with_progress(&glowing_room(), 8, 6, settings, |_, samples| {
+            calls += 1;
+            last_samples = samples;
+        });
+        assert!(calls > 0);
+        assert_eq!(last_samples, 20);
+        assert_glowing(matrix.get(3, 2));
+    }
 
-    vec.rotate(angle)
+    #[test]
+    fn resumed_renders_keep_their_samples() {
+        let mut resumed = ColorMatrix::new(8, 6);
+        for y in 0..6 {
+            for x in 0..8 {
+                resumed.set(x, y, Color::new(0.202, 0.402, 0.602) * 2.0);
+            }
+        }
+        let settings = TraceSettings {
+            threads: 2,
+            samples: Some(30),
+            ..TraceSettings::default()
+        };
+        let mut last_samples = 0;
+        let matrix = resume_path_trace_with_progress(
+            &glowing_room(),
+            &resumed,
+            10,
+            settings,
+            |_, samples| last_samples = samples,
+        );
+        assert_eq!(last_samples, 30);
+        // a third of the samples come from the resumed image, which is twice as bright
+        let expected = Color::new(0.202, 0.402, 0.602) * (4.0 / 3.0);
+        let actual = matrix.get(3, 2);
+        for (actual, expected) in actual.channels().iter().zip(expected.channels().iter()) {
+            assert!((actual - expected).abs() < 1e-9, "{:?}", matrix.get(3, 2));
+        }
+    }
+
+    #[test]
+    fn shadow_rays_have_to_reach_the_sampled_emitter() {
+        // a glowing unit sphere with a triangle in front of its lower half
+        let world = World::new(
+            vec![
+                (
+                    Entity::Sphere(Sphere::new(Point::new(0.0, 0.0, 0.0), 1.0)),
+                    Material::new_light(Color::WHITE, 1.0),
+                ),
+                (
+                    Entity::Triangle(Triangle::new(
+                        Point::new(-2.0, -2.0, -1.0),
+                        Point::new(2.0, -2.0, -1.0),
+                        Point::new(0.0, -0.5, -1.0),
+                    )),
+                    Material::new_diffuse(Color::WHITE),
+                ),
+            ],
+            vec![],
+        );
+        let point = Point::new(0.0, 0.0, -5.0);
+        let visible = |to: Point, entity: Option<usize>| {
+            let sample = LightSample {
+                direction: (to - point).normalize(),
+                distance: (to - point).len(),
+                irradiance: Color::WHITE,
+                entity,
+            };
+            is_visible(&world, point, &sample)
+        };
+        assert!(visible(Point::new(0.0, 0.0, -1.0), Some(0)));
+        // a point light would be visible there, the emitter is missed
+        assert!(visible(Point::new(0.0, 4.0, -5.0), None));
+        assert!(!visible(Point::new(0.0, 4.0, -5.0), Some(0)));
+        // the triangle is hit at the distance of the sample
+        assert!(!visible(Point::new(0.0, -1.0, -1.0), Some(0)));
+    }
+
+    #[test]
+    fn path_trace_stops_after_time_limit() {
+        let settings = TraceSettings {
+            threads: 2,
+            time_limit: Some(Duration::from_millis(200)),
+            ..TraceSettings::default()
+        };
+        let start = Instant::now();
+        let matrix = path_trace(&glowing_room(), 8, 6, settings);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_glowing(matrix.get(7, 5));
+    }
 }
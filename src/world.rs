@@ -1,22 +1,190 @@
+use rand::Rng;
+
 use crate::{
+    bvh::{self, Bvh},
+    camera::Camera,
+    drawing::Color,
     entities::{Entity, IntersectionResult},
     geometry::Point,
+    light::Light,
     material::Material,
     Float,
 };
 
+// materials with less light than this do not emit
+const EMISSIVE: Float = 0.00001;
+
 #[derive(Clone)]
 pub struct World {
     entities: Vec<(Entity, Material)>,
-    pub light: Vec<Point>,
+    // the scene object of every entity, all faces of a cube are one object
+    objects: Vec<usize>,
+    bvh: Bvh,
+    unbounded: Vec<usize>,
+    // emissive surfaces that can be sampled, with the cumulative distribution to pick them by power
+    emitters: Vec<Emitter>,
+    emitter_cdf: Vec<Float>,
+    emitter_power: Float,
+    pub light: Vec<Light>,
+    pub ambient: Ambient,
+    pub camera: Camera,
+}
+
+// light that reaches every point from everywhere, only the ray tracer uses it
+#[derive(Clone, Copy, Debug)]
+pub struct Ambient {
+    pub color: Color,
+    pub intensity: Float,
+}
+
+impl Default for Ambient {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 0.1,
+        }
+    }
 }
 
 impl World {
-    pub fn new(entities: Vec<(Entity, Material)>, light: Vec<Point>) -> Self {
-        Self { entities, light }
+    pub fn new(entities: Vec<(Entity, Material)>, light: Vec<Light>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for (i, (entity, _)) in entities.iter().enumerate() {
+            match entity.bounds() {
+                Some(bounds) => bounded.push((i, bounds)),
+                None => unbounded.push(i),
+            }
+        }
+        let mut emitters = Vec::new();
+        let mut emitter_cdf = Vec::new();
+        let mut total_power = 0.0;
+        for (i, (entity, material)) in entities.iter().enumerate() {
+            // meshes with materials of their own emit from the faces of every emissive material
+            let surfaces = match entity {
+                Entity::Mesh(mesh) if !mesh.materials().is_empty() => mesh
+                    .materials()
+                    .iter()
+                    .enumerate()
+                    .map(|(group, material)| {
+                        (Some(group), material.light, Some(mesh.material_area(group)))
+                    })
+                    .collect(),
+                _ => vec![(None, material.light, entity.area())],
+            };
+            for (group, light, area) in surfaces {
+                if light <= EMISSIVE {
+                    continue;
+                }
+                if let Some(area) = area.filter(|&area| area > 0.0) {
+                    total_power += area * light;
+                    emitters.push(Emitter { entity: i, group });
+                    emitter_cdf.push(total_power);
+                }
+            }
+        }
+        for value in emitter_cdf.iter_mut() {
+            *value /= total_power;
+        }
+        Self {
+            objects: (0..entities.len()).collect(),
+            entities,
+            bvh: Bvh::new(bounded),
+            unbounded,
+            emitters,
+            emitter_cdf,
+            emitter_power: total_power,
+            light,
+            ambient: Ambient::default(),
+            camera: Camera::default(),
+        }
+    }
+
+    // every entity is its own object unless they are grouped here, transparent objects are told
+    // apart by it when a ray leaves them
+    pub fn with_objects(mut self, objects: Vec<usize>) -> Self {
+        assert_eq!(objects.len(), self.entities.len(), "one object per entity");
+        self.objects = objects;
+        self
     }
 
-    pub fn cast_ray(&self, origin: Point, direction: Point) -> Option<CastResult> {
+    pub fn cast_ray(&self, origin: Point, direction: Point) -> Option<CastResult<'_>> {
+        let origin = origin + direction * 0.00001;
+        let mut closest: Option<(usize, IntersectionResult)> = None;
+        for &i in self.unbounded.iter() {
+            if let Some(intersection) = self.entities[i].0.intersect(origin, direction) {
+                if bvh::closer(&closest, i, &intersection) {
+                    closest = Some((i, intersection));
+                }
+            }
+        }
+        let max_distance = match &closest {
+            None => Float::INFINITY,
+            Some((_, intersection)) => intersection.distance,
+        };
+        self.bvh.traverse(origin, direction, max_distance, |i| {
+            let intersection = self.entities[i].0.intersect(origin, direction)?;
+            if bvh::closer(&closest, i, &intersection) {
+                let distance = intersection.distance;
+                closest = Some((i, intersection));
+                Some(distance)
+            } else {
+                None
+            }
+        });
+        closest.map(|(i, intersection)| {
+            let material = self.material_of(i, &intersection);
+            CastResult::new(intersection, material, i, self.objects[i])
+        })
+    }
+
+    // meshes report the material of the face that was hit, other entities have one material
+    fn material_of(&self, entity: usize, intersection: &IntersectionResult) -> &Material {
+        let (entity, material) = &self.entities[entity];
+        match (entity, intersection.material) {
+            (Entity::Mesh(mesh), Some(index)) => &mesh.materials()[index],
+            _ => material,
+        }
+    }
+
+    // picks an emissive surface proportionally to its power and a uniform point on it
+    pub fn sample_emitter<R: Rng>(&self, rng: &mut R) -> Option<EmitterSample> {
+        let x = rng.gen::<Float>();
+        let pick = self
+            .emitter_cdf
+            .iter()
+            .position(|&value| x < value)
+            .unwrap_or(self.emitters.len().checked_sub(1)?);
+        let emitter = &self.emitters[pick];
+        let (entity, material) = &self.entities[emitter.entity];
+        let ((point, normal), material) = match (entity, emitter.group) {
+            (Entity::Mesh(mesh), Some(group)) => (
+                mesh.sample_material_point(group, rng)?,
+                &mesh.materials()[group],
+            ),
+            _ => (entity.sample_point(rng)?, material),
+        };
+        Some(EmitterSample {
+            entity: emitter.entity,
+            point,
+            normal,
+            radiance: material.color * material.light,
+            pdf: self.emitter_pdf(emitter.entity, material),
+        })
+    }
+
+    // density of sample_emitter returning a given point of the entity, per unit area, material
+    // is the one of the point, a surface is picked with chance area * light / total power and
+    // then a point on it with density 1 / area
+    pub fn emitter_pdf(&self, entity: usize, material: &Material) -> Float {
+        if material.light <= EMISSIVE || self.entities[entity].0.area().is_none() {
+            return 0.0;
+        }
+        material.light / self.emitter_power
+    }
+
+    #[cfg(test)]
+    fn cast_ray_linear(&self, origin: Point, direction: Point) -> Option<CastResult<'_>> {
         let origin = origin + direction * 0.00001;
         let mut intersection = None;
         let mut distance = Float::INFINITY;
@@ -34,26 +202,164 @@ impl World {
                 }
             }
         }
-        match intersection {
-            None => None,
-            Some(real_intersection) => Some(CastResult::new(
+        intersection.map(|real_intersection| {
+            let material = self.material_of(entity_idx, &real_intersection);
+            CastResult::new(
                 real_intersection,
-                self.entities[entity_idx].1,
-            )),
-        }
+                material,
+                entity_idx,
+                self.objects[entity_idx],
+            )
+        })
     }
 }
 
-pub struct CastResult {
+// an emissive entity, or the faces of a mesh with the emissive material at index group
+#[derive(Clone)]
+struct Emitter {
+    entity: usize,
+    group: Option<usize>,
+}
+
+pub struct CastResult<'a> {
     pub intersection: IntersectionResult,
-    pub material: Material,
+    pub material: &'a Material,
+    // index of the entity that was hit
+    pub entity: usize,
+    // scene object the entity belongs to
+    pub object: usize,
 }
 
-impl CastResult {
-    fn new(intersection: IntersectionResult, material: Material) -> Self {
+impl<'a> CastResult<'a> {
+    fn new(
+        intersection: IntersectionResult,
+        material: &'a Material,
+        entity: usize,
+        object: usize,
+    ) -> Self {
         CastResult {
             intersection,
             material,
+            entity,
+            object,
+        }
+    }
+}
+
+pub struct EmitterSample {
+    // index of the entity the point lies on
+    pub entity: usize,
+    pub point: Point,
+    pub normal: Point,
+    pub radiance: Color,
+    // per unit area, includes the probability to pick this entity
+    pub pdf: Float,
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        drawing::Color,
+        entities::{Mesh, MeshBuffers, Plane, Sphere, Triangle},
+    };
+
+    fn random_point(rng: &mut StdRng, scale: Float) -> Point {
+        Point::new(
+            rng.gen_range(-scale, scale),
+            rng.gen_range(-scale, scale),
+            rng.gen_range(-scale, scale),
+        )
+    }
+
+    #[test]
+    fn bvh_matches_linear_cast() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut entities = Vec::new();
+        for i in 0..300 {
+            // light is used as a tag to tell which entity was hit
+            let material = Material::new(Color::WHITE, 0.9, 1.0, 0.0, 1.0, i as Float);
+            let entity = if i % 3 == 0 {
+                Entity::Sphere(Sphere::new(
+                    random_point(&mut rng, 20.0),
+                    rng.gen_range(0.1, 2.0),
+                ))
+            } else {
+                let p = random_point(&mut rng, 20.0);
+                Entity::Triangle(Triangle::new(
+                    p,
+                    p + random_point(&mut rng, 3.0),
+                    p + random_point(&mut rng, 3.0),
+                ))
+            };
+            entities.push((entity, material));
+        }
+        entities.push((
+            Entity::Plane(Plane::new(
+                Point::new(0.0, 15.0, 0.0),
+                Point::new(0.0, 15.0, 1.0),
+                Point::new(1.0, 15.0, 0.0),
+            )),
+            Material::new(Color::WHITE, 0.9, 1.0, 0.0, 1.0, -1.0),
+        ));
+        let world = World::new(entities, vec![]);
+
+        for _ in 0..5000 {
+            let origin = random_point(&mut rng, 25.0);
+            let direction = random_point(&mut rng, 1.0);
+            let expected = world.cast_ray_linear(origin, direction);
+            let actual = world.cast_ray(origin, direction);
+            match (expected, actual) {
+                (None, None) => {}
+                (Some(expected), Some(actual)) => {
+                    assert_eq!(expected.intersection.distance, actual.intersection.distance);
+                    assert_eq!(expected.material.light, actual.material.light);
+                    assert_eq!(expected.entity, actual.entity);
+                }
+                (expected, actual) => panic!(
+                    "linear hit: {}, bvh hit: {}",
+                    expected.is_some(),
+                    actual.is_some()
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn emissive_faces_of_meshes_are_sampled() {
+        // unit square split along its diagonal, only the face above it glows
+        let mut buffers = MeshBuffers::new(
+            vec![
+                Point::new(0.0, 0.0, 1.0),
+                Point::new(1.0, 0.0, 1.0),
+                Point::new(1.0, 1.0, 1.0),
+                Point::new(0.0, 1.0, 1.0),
+            ],
+            vec![[0, 1, 2], [0, 2, 3]],
+        );
+        buffers.materials = vec![
+            Material::new(Color::WHITE, 0.9, 1.0, 0.0, 1.0, 0.0),
+            Material::new_light(Color::WHITE, 2.0),
+        ];
+        buffers.face_materials = vec![0, 1];
+        let mesh = Entity::Mesh(Mesh::new(buffers).unwrap());
+        let world = World::new(vec![(mesh, Material::new_diffuse(Color::WHITE))], vec![]);
+
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let sample = world.sample_emitter(&mut rng).unwrap();
+            assert!(sample.point.y >= sample.point.x - 1e-9);
+            assert_eq!(sample.radiance, Color::WHITE * 2.0);
+            // the whole power comes from the half of the square
+            assert!((sample.pdf - 2.0).abs() < 1e-9);
         }
+        let glowing = Material::new_light(Color::WHITE, 2.0);
+        assert!((world.emitter_pdf(0, &glowing) - 2.0).abs() < 1e-9);
+        assert_eq!(
+            world.emitter_pdf(0, &Material::new_diffuse(Color::WHITE)),
+            0.0
+        );
     }
 }
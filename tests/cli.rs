@@ -0,0 +1,24 @@
+use std::process::Command;
+
+fn run(args: &[&str]) -> std::process::Output {
+    Command::new(env!("CARGO_BIN_EXE_ray-tracing-1"))
+        .args(args)
+        .output()
+        .expect("the binary runs")
+}
+
+#[test]
+fn usage_errors_exit_with_2() {
+    let output = run(&["--width", "wide"]);
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("error: invalid value 'wide' for --width"));
+    assert!(stderr.contains("usage: ray-tracing-1"));
+}
+
+#[test]
+fn help_exits_successfully() {
+    let output = run(&["--help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: ray-tracing-1"));
+}